@@ -1,9 +1,8 @@
-#![feature(env, old_io, old_path)]
-
 extern crate zip;
 
 use std::env;
-use std::old_io::File;
+use std::fs::File;
+use std::path::Path;
 use zip::ZipReader;
 use zip::fileinfo::FileInfo;
 
@@ -38,18 +37,12 @@ fn zip_file_info(zip: &mut ZipReader<File>, file: &str) -> FileInfo{
 }
 
 fn list_content(reader: &mut ZipReader<File>)->(){
-    for file in reader.files(){
-        let (year, month, day, hour, minute, second) = file.last_modified_datetime;
-        let mod_time = format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, hour, minute, second);
-        println!("{} ({}): bytes: {:10}, compressed: {:10}",
-            file.name, mod_time, file.compressed_size, file.uncompressed_size);
-    }
+    print!("{}", do_or_die!(reader.format_listing()));
 }
 
 fn extract_file(zip: &mut ZipReader<File>, file: &str)->(){
     let mut out = output_file(file);
-    let info = zip_file_info(zip, file);
-    do_or_die!(zip.extract_file(&info, &mut out));
+    do_or_die!(zip.extract_by_name(file, &mut out));
 }
 
 fn extract_head(zip: &mut ZipReader<File>, file: &str, length: &str)->(){
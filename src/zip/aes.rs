@@ -0,0 +1,74 @@
+//! WinZip AES (AE-1/AE-2) decryption.
+
+use crypto::aessafe::{AesSafe128Encryptor, AesSafe192Encryptor, AesSafe256Encryptor};
+use crypto::hmac::Hmac;
+use crypto::mac::{Mac, MacResult};
+use crypto::pbkdf2::pbkdf2;
+use crypto::sha1::Sha1;
+use crypto::symmetriccipher::BlockEncryptor;
+use error::{ZipError, ZipResult};
+use format::AesStrength;
+use maybe_utf8::MaybeUtf8Buf;
+
+static PBKDF2_ITERATIONS: u32 = 1000;
+static PASSWORD_VERIFIER_LENGTH: usize = 2;
+static AUTH_CODE_LENGTH: usize = 10;
+
+/// Decrypts the data of an AES-encrypted entry, laid out as
+/// `[salt][password verifier][encrypted data][authentication code]`.
+/// The returned bytes are still compressed with the entry's real compression method.
+pub fn decrypt(strength: AesStrength, password: &[u8], data: &[u8]) -> ZipResult<Vec<u8>> {
+    let key_length = strength.key_length();
+    let salt_length = strength.salt_length();
+    if data.len() < salt_length + PASSWORD_VERIFIER_LENGTH + AUTH_CODE_LENGTH {
+        return Err(ZipError::DecompressionFailure(MaybeUtf8Buf::new()));
+    }
+    let (salt, rest) = data.split_at(salt_length);
+    let (verifier, rest) = rest.split_at(PASSWORD_VERIFIER_LENGTH);
+    let (encrypted, auth_code) = rest.split_at(rest.len() - AUTH_CODE_LENGTH);
+
+    // the derived key material is the encryption key, the HMAC key and the password verifier
+    let mut derived = vec![0u8; 2 * key_length + PASSWORD_VERIFIER_LENGTH];
+    pbkdf2(&mut Hmac::new(Sha1::new(), password), salt, PBKDF2_ITERATIONS, &mut derived);
+    let (encryption_key, rest) = derived.split_at(key_length);
+    let (auth_key, derived_verifier) = rest.split_at(key_length);
+    if derived_verifier != verifier {
+        return Err(ZipError::InvalidPassword);
+    }
+
+    // the authentication code covers the encrypted data, so check it before decrypting
+    let mut hmac = Hmac::new(Sha1::new(), auth_key);
+    hmac.input(encrypted);
+    let code = hmac.result();
+    if MacResult::new(&code.code()[..AUTH_CODE_LENGTH]) != MacResult::new(auth_code) {
+        return Err(ZipError::AuthenticationError);
+    }
+
+    let mut decrypted = encrypted.to_vec();
+    match strength {
+        AesStrength::Aes128 => apply_ctr(&AesSafe128Encryptor::new(encryption_key), &mut decrypted),
+        AesStrength::Aes192 => apply_ctr(&AesSafe192Encryptor::new(encryption_key), &mut decrypted),
+        AesStrength::Aes256 => apply_ctr(&AesSafe256Encryptor::new(encryption_key), &mut decrypted),
+    }
+    Ok(decrypted)
+}
+
+// WinZip uses CTR mode with a little-endian counter starting at 1 and no nonce.
+fn apply_ctr<C: BlockEncryptor>(cipher: &C, data: &mut [u8]) {
+    let mut counter = [0u8; 16];
+    let mut keystream = [0u8; 16];
+    for chunk in data.chunks_mut(16) {
+        for byte in counter.iter_mut() {
+            if *byte == 0xff {
+                *byte = 0;
+            } else {
+                *byte += 1;
+                break;
+            }
+        }
+        cipher.encrypt_block(&counter, &mut keystream);
+        for (b, k) in chunk.iter_mut().zip(keystream.iter()) {
+            *b ^= *k;
+        }
+    }
+}
@@ -68,7 +68,13 @@ static CRCTABLE : [u32; 256] = [
 /// Takes a buffer of bytes (u8 vector) and computes its CRC32 as used
 /// in PKZip.
 pub fn crc32(buf: &[u8]) -> u32 {
-    let mut r = 0xffffffffu32;
+    update(0, buf)
+}
+
+/// Continues the CRC32 `crc` of the bytes before `buf` over `buf`,
+/// so that data can be checked a chunk at a time.
+pub fn update(crc: u32, buf: &[u8]) -> u32 {
+    let mut r = crc ^ 0xffffffffu32;
     for &byte in buf.iter() {
         let idx = byte ^ ((r & 0xff) as u8);
         r = (r >> 8) ^ CRCTABLE[idx as usize];
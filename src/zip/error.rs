@@ -1,43 +1,147 @@
 //! A list of possible errors.
 
+use std::error::Error;
 use std::fmt;
-use std::old_io::IoError;
+use std::io;
+use maybe_utf8::MaybeUtf8Buf;
 
-/// A list of possible errors. This is a supetset of `std::Io::IoError`.
-#[derive(PartialEq, Clone, Debug)]
+/// A list of possible errors. This is a supetset of `std::io::Error`.
+#[derive(Debug)]
 pub enum ZipError {
-    IoError(IoError),
+    IoError(io::Error),
     NotAZipFile,
-    CrcError,
-    DecompressionFailure,
-    FileNotFoundInArchive,
-    InvalidSignature(u32),
+    // these four carry the name of the entry, or an empty name where it isn't known
+    CrcError(MaybeUtf8Buf),
+    DecompressionFailure(MaybeUtf8Buf),
+    FileNotFoundInArchive(MaybeUtf8Buf),
+    // and what was decompressed before the failure
+    PartiallyDecompressed(MaybeUtf8Buf, Vec<u8>),
+    // the signature found, and where if known
+    InvalidSignature(u32, Option<u64>),
+    HeaderMismatch,
+    UnsupportedCompressionMethod(u16),
     NonUTF8Field,
     TooLongField,
+    Encrypted,
+    UnsupportedEncryption,
+    InvalidPassword,
+    AuthenticationError,
+    Cancelled,
+    UnsupportedMultiDisk,
+    DecompressedSizeLimitExceeded,
+    InvalidDateTime,
+    UnsupportedVersion(u16),
+    InvalidOffset(u64),
+    EntryTooLarge,
 }
 
 impl fmt::Display for ZipError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            ZipError::IoError(ref e) => write!(f, "{}", e.detail.clone().unwrap_or(String::from_str(e.desc))),
+            ZipError::IoError(ref e) => write!(f, "{}", e),
             ZipError::NotAZipFile => write!(f, "not a ZIP file"),
-            ZipError::CrcError => write!(f, "CRC mismatch"),
-            ZipError::DecompressionFailure => write!(f, "decompression failure"),
-            ZipError::FileNotFoundInArchive => write!(f, "file not found in archive"),
-            ZipError::InvalidSignature(magic) => write!(f, "invalid ZIP signature {:#08x}", magic),
+            ZipError::CrcError(ref name) => write_with_name(f, "CRC mismatch", name),
+            ZipError::DecompressionFailure(ref name) => write_with_name(f, "decompression failure", name),
+            ZipError::FileNotFoundInArchive(ref name) => write_with_name(f, "file not found in archive", name),
+            ZipError::PartiallyDecompressed(ref name, ref partial) =>
+                write_with_name(f, &format!("decompression failure after {} bytes", partial.len()), name),
+            ZipError::InvalidSignature(magic, None) => write!(f, "invalid ZIP signature {:#08x}", magic),
+            ZipError::InvalidSignature(magic, Some(offset)) =>
+                write!(f, "invalid ZIP signature {:#08x} at offset {}", magic, offset),
+            ZipError::HeaderMismatch => write!(f, "local file header doesn't match the central directory"),
+            ZipError::UnsupportedCompressionMethod(method) =>
+                write!(f, "unsupported compression method {}", method),
             ZipError::NonUTF8Field =>
                 write!(f, "file name or comment is set to UTF-8 encoded but it isn't"),
             ZipError::TooLongField =>
                 write!(f, "file name, comment or extra field is too long (> 64KB)"),
+            ZipError::Encrypted => write!(f, "file is encrypted and no password was given"),
+            ZipError::UnsupportedEncryption =>
+                write!(f, "file is encrypted with a method other than WinZip AES, which is not supported"),
+            ZipError::InvalidPassword => write!(f, "invalid password"),
+            ZipError::AuthenticationError => write!(f, "authentication code mismatch"),
+            ZipError::Cancelled => write!(f, "operation cancelled"),
+            ZipError::UnsupportedMultiDisk => write!(f, "multi-disk (spanned) archives are not supported"),
+            ZipError::DecompressedSizeLimitExceeded =>
+                write!(f, "entry decompresses to more than its declared size or the configured limit"),
+            ZipError::InvalidDateTime =>
+                write!(f, "date or time can't be stored as an MS-DOS timestamp (1980-2107)"),
+            ZipError::UnsupportedVersion(version) =>
+                write!(f, "entry needs version {}.{} of the ZIP specification to extract",
+                       version / 10, version % 10),
+            ZipError::InvalidOffset(offset) =>
+                write!(f, "entry at offset {} overlaps the central directory or lies past it", offset),
+            ZipError::EntryTooLarge => write!(f, "entries written with a data descriptor that grow past 4 GB have to be started with FileOptions::large_file"),
         }
     }
 }
 
-pub type ZipResult<T> = Result<T, ZipError>;
+fn write_with_name(f: &mut fmt::Formatter, message: &str, name: &MaybeUtf8Buf) -> fmt::Result {
+    if name.len() == 0 {
+        write!(f, "{}", message)
+    } else {
+        write!(f, "{}: {}", message, name)
+    }
+}
 
-macro_rules! try_io {
-    ($e:expr) => (try!($e.map_err(::error::ZipError::IoError)))
+impl ZipError {
+    /// Fills in the entry name of the errors that carry one, unless it is already set.
+    pub fn with_name(self, entry: &MaybeUtf8Buf) -> ZipError {
+        match self {
+            ZipError::CrcError(ref name) if name.len() == 0 => ZipError::CrcError(entry.clone()),
+            ZipError::DecompressionFailure(ref name) if name.len() == 0 =>
+                ZipError::DecompressionFailure(entry.clone()),
+            ZipError::FileNotFoundInArchive(ref name) if name.len() == 0 =>
+                ZipError::FileNotFoundInArchive(entry.clone()),
+            ZipError::PartiallyDecompressed(name, partial) => {
+                let name = if name.len() == 0 { entry.clone() } else { name };
+                ZipError::PartiallyDecompressed(name, partial)
+            }
+            e => e
+        }
+    }
 }
 
+impl Error for ZipError {
+    fn description(&self) -> &str {
+        match *self {
+            ZipError::IoError(ref e) => e.description(),
+            ZipError::NotAZipFile => "not a ZIP file",
+            ZipError::CrcError(..) => "CRC mismatch",
+            ZipError::DecompressionFailure(..) => "decompression failure",
+            ZipError::FileNotFoundInArchive(..) => "file not found in archive",
+            ZipError::PartiallyDecompressed(..) => "decompression failure",
+            ZipError::InvalidSignature(..) => "invalid ZIP signature",
+            ZipError::HeaderMismatch => "local file header doesn't match the central directory",
+            ZipError::UnsupportedCompressionMethod(..) => "unsupported compression method",
+            ZipError::NonUTF8Field => "field is set to UTF-8 encoded but it isn't",
+            ZipError::TooLongField => "field is too long",
+            ZipError::Encrypted => "file is encrypted",
+            ZipError::UnsupportedEncryption => "unsupported encryption method",
+            ZipError::InvalidPassword => "invalid password",
+            ZipError::AuthenticationError => "authentication code mismatch",
+            ZipError::Cancelled => "operation cancelled",
+            ZipError::UnsupportedMultiDisk => "multi-disk archives are not supported",
+            ZipError::DecompressedSizeLimitExceeded => "decompressed size limit exceeded",
+            ZipError::InvalidDateTime => "invalid MS-DOS date or time",
+            ZipError::UnsupportedVersion(..) => "unsupported ZIP specification version",
+            ZipError::InvalidOffset(..) => "entry offset out of bounds",
+            ZipError::EntryTooLarge => "entry too large",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ZipError::IoError(ref e) => Some(e as &Error),
+            _ => None
+        }
+    }
+}
 
+impl From<io::Error> for ZipError {
+    fn from(e: io::Error) -> ZipError {
+        ZipError::IoError(e)
+    }
+}
 
+pub type ZipResult<T> = Result<T, ZipError>;
@@ -1,53 +1,294 @@
+use std::fmt;
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::ffi::OsStr;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use crc32;
+use error::{ZipError, ZipResult};
 use format;
 use maybe_utf8::MaybeUtf8Buf;
+use names::MaybeUtf8Ext;
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum CompressionMethod {
-    Store=0,
-    Deflate=8,
-    Unknown
+    Store,
+    Deflate,
+    Deflate64,
+    Zstd,
+    Unknown(u16)
 }
 
 impl CompressionMethod {
     pub fn from_u16(x: u16) -> CompressionMethod {
-        let u = x as usize;
-        if      u == (CompressionMethod::Store   as usize) { CompressionMethod::Store }
-        else if u == (CompressionMethod::Deflate as usize) { CompressionMethod::Deflate }
-        else                                               { CompressionMethod::Unknown }
+        match x {
+            0  => CompressionMethod::Store,
+            8  => CompressionMethod::Deflate,
+            9  => CompressionMethod::Deflate64,
+            93 => CompressionMethod::Zstd,
+            x  => CompressionMethod::Unknown(x)
+        }
+    }
+
+    pub fn to_u16(&self) -> u16 {
+        match *self {
+            CompressionMethod::Store      => 0,
+            CompressionMethod::Deflate    => 8,
+            CompressionMethod::Deflate64  => 9,
+            CompressionMethod::Zstd       => 93,
+            CompressionMethod::Unknown(x) => x
+        }
+    }
+}
+
+/// The system an entry was made on, from the upper byte of `version_made_by`. It tells
+/// how to read the external file attributes.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum HostOs {
+    MsDos,
+    Amiga,
+    OpenVms,
+    Unix,
+    VmCms,
+    AtariSt,
+    Os2,
+    Macintosh,
+    ZSystem,
+    CpM,
+    WindowsNtfs,
+    Mvs,
+    Vse,
+    AcornRisc,
+    Vfat,
+    AlternateMvs,
+    BeOs,
+    Tandem,
+    Os400,
+    OsX,
+    Unknown(u8)
+}
+
+impl HostOs {
+    pub fn from_u8(x: u8) -> HostOs {
+        match x {
+            0  => HostOs::MsDos,
+            1  => HostOs::Amiga,
+            2  => HostOs::OpenVms,
+            3  => HostOs::Unix,
+            4  => HostOs::VmCms,
+            5  => HostOs::AtariSt,
+            6  => HostOs::Os2,
+            7  => HostOs::Macintosh,
+            8  => HostOs::ZSystem,
+            9  => HostOs::CpM,
+            10 => HostOs::WindowsNtfs,
+            11 => HostOs::Mvs,
+            12 => HostOs::Vse,
+            13 => HostOs::AcornRisc,
+            14 => HostOs::Vfat,
+            15 => HostOs::AlternateMvs,
+            16 => HostOs::BeOs,
+            17 => HostOs::Tandem,
+            18 => HostOs::Os400,
+            19 => HostOs::OsX,
+            x  => HostOs::Unknown(x)
+        }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct FileInfo {
     pub name:               MaybeUtf8Buf,
     pub compression_method: CompressionMethod,
     // (year, month, day, hour, minute, second)
     pub last_modified_datetime: (usize, usize, usize, usize, usize, usize),
     pub crc32:              u32,
-    pub compressed_size:    u32,
-    pub uncompressed_size:  u32,
+    pub compressed_size:    u64,
+    pub uncompressed_size:  u64,
     pub is_encrypted:       bool,
+    pub version_made_by: u16,
+    pub version_needed_to_extract: u16,
+    pub internal_file_attributes: u16,
+    pub external_file_attributes: u32,
+    pub disk_number_start: u16,
 
-    pub local_file_header_offset: u32,
+    pub local_file_header_offset: u64,
 }
 
 impl FileInfo {
     // fills a FileInfo struct with the file properties, for users of the external API to see
     pub fn from_cdh(h: &format::CentralDirectoryHeader) -> FileInfo {
-        let method : CompressionMethod =
-            if h.compression_method == 0 { CompressionMethod::Store }
-            else if h.compression_method == 8 { CompressionMethod::Deflate }
-            else { panic!() };
+        // AES-encrypted entries keep their real compression method in the AE-x extra field
+        let method_code = match format::AesExtraField::from_extra_field(&h.extra_field) {
+            Some(ref aes) if h.compression_method == format::AES_COMPRESSION_METHOD =>
+                aes.compression_method,
+            _ => h.compression_method
+        };
+        let (uncompressed_size, compressed_size, offset) = h.zip64_fields();
+        FileInfo {
+            name:               name_of(h.unicode_path(), &h.file_name),
+            compression_method: CompressionMethod::from_u16(method_code),
+            last_modified_datetime: h.last_modified_datetime.to_tuple(),
+            crc32:              h.crc32,
+            compressed_size:    compressed_size,
+            uncompressed_size:  uncompressed_size,
+            local_file_header_offset: offset,
+            is_encrypted:       h.is_encrypted(),
+            version_made_by: h.version_made_by,
+            version_needed_to_extract: h.version_needed_to_extract,
+            internal_file_attributes: h.internal_file_attributes,
+            external_file_attributes: h.external_file_attributes,
+            disk_number_start: h.disk_number_start,
+        }
+    }
+
+    // as above, for readers that never get to see the central directory
+    pub fn from_local_header(h: &format::LocalFileHeader, offset: u64) -> FileInfo {
+        let method_code = match format::AesExtraField::from_extra_field(&h.extra_field) {
+            Some(ref aes) if h.compression_method == format::AES_COMPRESSION_METHOD =>
+                aes.compression_method,
+            _ => h.compression_method
+        };
+        let (uncompressed_size, compressed_size) = h.zip64_sizes()
+            .unwrap_or((h.uncompressed_size as u64, h.compressed_size as u64));
         FileInfo {
-            name:               h.file_name.clone(),
-            compression_method: method,
+            name:               name_of(h.unicode_path(), &h.file_name),
+            compression_method: CompressionMethod::from_u16(method_code),
             last_modified_datetime: h.last_modified_datetime.to_tuple(),
             crc32:              h.crc32,
-            compressed_size:    h.compressed_size,
-            uncompressed_size:  h.uncompressed_size,
-            local_file_header_offset: h.relative_offset_of_local_header,
+            compressed_size:    compressed_size,
+            uncompressed_size:  uncompressed_size,
+            local_file_header_offset: offset,
             is_encrypted:       h.is_encrypted(),
+            // only the central directory has these, an MS-DOS host is the default
+            version_made_by: 0,
+            version_needed_to_extract: h.version_needed_to_extract,
+            internal_file_attributes: 0,
+            external_file_attributes: 0,
+            disk_number_start: 0,
+        }
+    }
+
+    /// Returns `last_modified_datetime` as seconds since the Unix epoch, with the
+    /// same caveat about time zones as `MsdosDateTime::to_unix_time`.
+    pub fn last_modified_unix_time(&self) -> i64 {
+        let (year, month, day, hour, minute, second) = self.last_modified_datetime;
+        format::MsdosDateTime::new(year, month, day, hour, minute, second).to_unix_time()
+    }
+
+    /// Returns the version of the specification needed to extract the entry, times ten
+    /// (e.g. 20 for 2.0). The upper byte, which some archivers fill with the host system,
+    /// is left out.
+    pub fn requires_version(&self) -> u16 {
+        self.version_needed_to_extract & 0xff
+    }
+
+    /// Returns the system the entry was made on.
+    pub fn host_os(&self) -> HostOs {
+        HostOs::from_u8((self.version_made_by >> 8) as u8)
+    }
+
+    /// Returns the Unix mode (file type and permissions) kept in the upper half of the
+    /// external attributes, for entries made on Unix or OS X that have one.
+    pub fn unix_mode(&self) -> Option<u32> {
+        match self.host_os() {
+            HostOs::Unix | HostOs::OsX if self.external_file_attributes >> 16 != 0 =>
+                Some(self.external_file_attributes >> 16),
+            _ => None
+        }
+    }
+
+    /// The archiver flagged the entry as text, which `unzip -a` takes as a cue to convert
+    /// line endings. Many archivers never set the flag.
+    pub fn is_text(&self) -> bool {
+        self.internal_file_attributes & 1 != 0
+    }
+
+    /// Returns the name as a string. Names the reader decoded, because they had the UTF-8
+    /// flag or through `ZipReaderOptions::name_decoder`, are returned as they are;
+    /// other names are taken as UTF-8, with replacement characters for invalid bytes.
+    pub fn decoded_name(&self) -> String {
+        self.name.as_cow_lossy().into_owned()
+    }
+
+    /// Directory entries are stored with a trailing slash.
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with_char('/')
+    }
+
+    /// Returns the compressed size as a fraction of the uncompressed size, so smaller is
+    /// better. Empty entries have a ratio of 1.0.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.uncompressed_size == 0 {
+            return 1.0;
+        }
+        self.compressed_size as f64 / self.uncompressed_size as f64
+    }
+
+    /// Stored entries can be read as they are, without decompressing.
+    pub fn is_stored(&self) -> bool {
+        self.compression_method == CompressionMethod::Store
+    }
+
+    /// Returns the entry name as a path, unchanged; see `sanitized_path` for one that is safe
+    /// to extract to. Returns `None` if the name has a null byte, which no platform allows.
+    /// Names that aren't UTF-8 keep their bytes on Unix and are decoded lossily elsewhere.
+    pub fn to_path(&self) -> Option<PathBuf> {
+        if self.name.as_bytes().contains(&0) {
+            return None;
+        }
+        Some(name_to_path(self))
+    }
+
+    /// Returns the entry name as a relative path, dropping any root, `.` and `..` components
+    /// so that it can't escape the directory it is extracted into. Both `/` and `\` are
+    /// treated as separators. Returns `None` if no component is left.
+    pub fn sanitized_path(&self) -> Option<PathBuf> {
+        let name = self.name.as_cow_lossy();
+        let mut path = PathBuf::new();
+        let mut empty = true;
+        for component in name.split(|c: char| c == '/' || c == '\\') {
+            match component {
+                "" | "." | ".." => {}
+                component => { path.push(component); empty = false; }
+            }
         }
+        if empty { None } else { Some(path) }
+    }
+}
+
+/// Checks `data`, the contents of the entry `f` obtained by other means than `ZipReader`
+/// (e.g. from `read_raw` and a decompressor of one's own), against the entry's crc32.
+pub fn verify_crc(f: &FileInfo, data: &[u8]) -> ZipResult<()> {
+    if crc32::crc32(data) != f.crc32 {
+        return Err(ZipError::CrcError(f.name.clone()));
+    }
+    Ok(())
+}
+
+// e.g. "dir/a.txt (Deflate, 120 -> 80 bytes, 2015-06-01 12:30:04)"
+impl fmt::Display for FileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day, hour, minute, second) = self.last_modified_datetime;
+        write!(f, "{} ({:?}, {} -> {} bytes, {}-{:02}-{:02} {:02}:{:02}:{:02})",
+               self.name, self.compression_method, self.uncompressed_size, self.compressed_size,
+               year, month, day, hour, minute, second)
+    }
+}
+
+fn name_of(unicode_path: Option<String>, name: &MaybeUtf8Buf) -> MaybeUtf8Buf {
+    match unicode_path {
+        Some(path) => MaybeUtf8Buf::from_str(path),
+        None => name.clone()
     }
 }
 
+#[cfg(unix)]
+fn name_to_path(f: &FileInfo) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(f.name.as_bytes()))
+}
+
+#[cfg(not(unix))]
+fn name_to_path(f: &FileInfo) -> PathBuf {
+    PathBuf::from(&f.name.as_cow_lossy()[..])
+}
@@ -3,14 +3,16 @@
 #![allow(missing_copy_implementations)]
 
 use std::fmt;
-use std::old_io::IoResult;
-use std::num::ToPrimitive;
+use std::io;
+use std::io::{Read, Write};
 use error::{ZipError, ZipResult};
+use util::{ReadExt, WriteExt};
+use crc32;
 use maybe_utf8::{MaybeUtf8Buf, MaybeUtf8Slice};
 
-fn read_maybe_utf8<T: Reader>(r: &mut T, should_be_utf8: bool,
+fn read_maybe_utf8<T: Read>(r: &mut T, should_be_utf8: bool,
                               len: usize) -> ZipResult<MaybeUtf8Buf> {
-    let v = try_io!(r.read_exact(len));
+    let v = try!(r.read_vec(len));
     if should_be_utf8 {
         match String::from_utf8(v) {
             Ok(s) => Ok(MaybeUtf8Buf::from_str(s)),
@@ -21,22 +23,23 @@ fn read_maybe_utf8<T: Reader>(r: &mut T, should_be_utf8: bool,
     }
 }
 
-fn write_maybe_utf8<T:Writer>(w: &mut T, should_be_utf8: bool, s: MaybeUtf8Slice) -> ZipResult<()> {
+fn write_maybe_utf8<T:Write>(w: &mut T, should_be_utf8: bool, s: MaybeUtf8Slice) -> ZipResult<()> {
     if should_be_utf8 {
         match s.as_str() {
-            Some(s) => try_io!(w.write_all(s.as_bytes())),
+            Some(s) => try!(w.write_all(s.as_bytes())),
             None => return Err(ZipError::NonUTF8Field),
         }
     } else {
-        try_io!(w.write_all(s.as_bytes()));
+        try!(w.write_all(s.as_bytes()));
     }
     Ok(())
 }
 
 fn ensure_u16_field_length(len: usize) -> ZipResult<u16> {
-    match len.to_u16() {
-        Some(v) => Ok(v),
-        None => Err(ZipError::TooLongField),
+    if len > 0xffff {
+        Err(ZipError::TooLongField)
+    } else {
+        Ok(len as u16)
     }
 }
 
@@ -49,10 +52,11 @@ pub struct MsdosDateTime {
 }
 
 impl MsdosDateTime {
+    /// Packs the fields without checking them; out of range values wrap around.
+    /// See `try_new` for the checked version.
     pub fn new(year: usize, month: usize, day: usize,
                hour: usize, minute: usize, second: usize) -> MsdosDateTime {
-        // XXX no strict error check
-        let year = year - 1980;
+        let year = year.wrapping_sub(1980);
         MsdosDateTime {
             time: (((hour & 0b11111) << 11) |
                    ((minute & 0b111111) << 5) |
@@ -63,6 +67,19 @@ impl MsdosDateTime {
         }
     }
 
+    /// As `new`, but fails with `InvalidDateTime` unless the year is within 1980-2107,
+    /// month 1-12, day 1-31, hour 0-23, minute 0-59 and second 0-58.
+    /// Seconds are stored halved, so odd seconds are rounded down.
+    pub fn try_new(year: usize, month: usize, day: usize,
+                   hour: usize, minute: usize, second: usize) -> ZipResult<MsdosDateTime> {
+        if year < 1980 || year > 2107 || month < 1 || month > 12 ||
+                day < 1 || day > days_in_month(year, month) ||
+                hour > 23 || minute > 59 || second > 58 {
+            return Err(ZipError::InvalidDateTime);
+        }
+        Ok(MsdosDateTime::new(year, month, day, hour, minute, second))
+    }
+
     pub fn zero() -> MsdosDateTime {
         MsdosDateTime { time: 0, date: 0 }
     }
@@ -72,25 +89,81 @@ impl MsdosDateTime {
     pub fn day   (&self) -> usize { ( self.date        &   0b11111) as usize }
     pub fn hour  (&self) -> usize { ((self.time >> 11) &   0b11111) as usize }
     pub fn minute(&self) -> usize { ((self.time >>  5) &  0b111111) as usize }
-    pub fn second(&self) -> usize { ((self.time <<  1) &  0b111111) as usize }
+    pub fn second(&self) -> usize { ( self.time        &   0b11111) as usize * 2 }
 
     pub fn to_tuple(&self) -> (usize, usize, usize, usize, usize, usize) {
         (self.year(), self.month(), self.day(), self.hour(), self.minute(), self.second())
     }
 
-    pub fn read<T:Reader>(r: &mut T) -> IoResult<MsdosDateTime> {
+    /// Returns the seconds since the Unix epoch. MS-DOS timestamps carry no time zone and are
+    /// normally local time, so this is the timestamp they would have if they were UTC;
+    /// subtract the local UTC offset to get the real one.
+    pub fn to_unix_time(&self) -> i64 {
+        let days = days_from_civil(self.year() as i64, self.month() as i64, self.day() as i64);
+        days * 86400 + (self.hour() * 3600 + self.minute() * 60 + self.second()) as i64
+    }
+
+    /// The inverse of `to_unix_time`, rounding odd seconds down.
+    /// Fails with `InvalidDateTime` for times outside of 1980-2107.
+    pub fn from_unix_time(t: i64) -> ZipResult<MsdosDateTime> {
+        if t < 0 {
+            return Err(ZipError::InvalidDateTime);
+        }
+        let (year, month, day) = civil_from_days(t / 86400);
+        let secs = (t % 86400) as usize;
+        MsdosDateTime::try_new(year as usize, month as usize, day as usize,
+                               secs / 3600, secs / 60 % 60, secs % 60 & !1)
+    }
+
+    pub fn read<T:Read>(r: &mut T) -> io::Result<MsdosDateTime> {
         let time = try!(r.read_le_u16());
         let date = try!(r.read_le_u16());
         Ok(MsdosDateTime { time: time, date: date })
     }
 
-    pub fn write<T:Writer>(&self, w: &mut T) -> IoResult<()> {
+    pub fn write<T:Write>(&self, w: &mut T) -> io::Result<()> {
         try!(w.write_le_u16(self.time));
         try!(w.write_le_u16(self.date));
         Ok(())
     }
 }
 
+// the length of month 1-12 of year in the Gregorian calendar; of the years MS-DOS dates
+// can hold, 2100 is the one divisible by 4 that is not a leap year
+fn days_in_month(year: usize, month: usize) -> usize {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31
+    }
+}
+
+// days between 1970-01-01 and the given date of the proleptic Gregorian calendar,
+// see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// the inverse of days_from_civil, for days >= 0
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
 impl fmt::Debug for MsdosDateTime {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}-{:02}-{:02} {:02}:{:02}:{:02}",
@@ -156,9 +229,39 @@ impl LocalFileHeader {
     pub fn has_utf8_name(&self) -> bool              { (self.general_purpose_bit_flag & 2048) != 0 }
     pub fn uses_masking(&self) -> bool               { (self.general_purpose_bit_flag & 8192) != 0 }
 
-    pub fn total_size(&self) -> usize {
+    pub fn is_zip64(&self) -> bool {
+        find_extra_field(&self.extra_field, ZIP64_EXTRA_FIELD_ID).is_some()
+    }
+
+    // returns (uncompressed, compressed) with the sizes set to 0xFFFFFFFF taken from the
+    // Zip64 extra field, which lists them in that order; None if a size can't be found
+    pub fn zip64_sizes(&self) -> Option<(u64, u64)> {
+        let mut data = match find_extra_field(&self.extra_field, ZIP64_EXTRA_FIELD_ID) {
+            Some(data) => data,
+            None => return None
+        };
+        let uncompressed = if self.uncompressed_size == 0xffffffff {
+            match data.read_le_u64() { Ok(x) => x, Err(_) => return None }
+        } else {
+            self.uncompressed_size as u64
+        };
+        let compressed = if self.compressed_size == 0xffffffff {
+            match data.read_le_u64() { Ok(x) => x, Err(_) => return None }
+        } else {
+            self.compressed_size as u64
+        };
+        Some((uncompressed, compressed))
+    }
+
+    // the name from a valid Unicode Path extra field, which takes precedence
+    pub fn unicode_path(&self) -> Option<String> {
+        parse_unicode_path_extra(&self.extra_field, self.file_name.as_bytes())
+    }
+
+    // computed in u64, so that it can be added to file offsets as it is on any target
+    pub fn total_size(&self) -> u64 {
         let local_file_header_fixed_size = 30;
-        local_file_header_fixed_size + self.file_name.len() + self.extra_field.len()
+        local_file_header_fixed_size + self.file_name.len() as u64 + self.extra_field.len() as u64
     }
 
     // -- constructors
@@ -177,49 +280,60 @@ impl LocalFileHeader {
     }
 
     // reads a LocalFileHeader from the current position of the reader r
-    pub fn read<T:Reader>(r: &mut T) -> ZipResult<LocalFileHeader> {
-        let mut h = LocalFileHeader::new();
-
-        let magic = try_io!(r.read_le_u32());
+    pub fn read<T:Read>(r: &mut T) -> ZipResult<LocalFileHeader> {
+        let magic = try!(r.read_le_u32());
         if magic != LFH_SIGNATURE {
-            return Err(ZipError::InvalidSignature(magic));
+            return Err(ZipError::InvalidSignature(magic, None));
         }
+        LocalFileHeader::read_after_signature(r)
+    }
 
-        h.version_needed_to_extract = try_io!(r.read_le_u16());
-        h.general_purpose_bit_flag = try_io!(r.read_le_u16());
-        h.compression_method = try_io!(r.read_le_u16());
-        h.last_modified_datetime = try_io!(MsdosDateTime::read(r));
-        h.crc32 = try_io!(r.read_le_u32());
-        h.compressed_size = try_io!(r.read_le_u32());
-        h.uncompressed_size = try_io!(r.read_le_u32());
-        let file_name_length = try_io!(r.read_le_u16()) as usize;
-        let extra_field_length = try_io!(r.read_le_u16()) as usize;
-        h.file_name = try!(read_maybe_utf8(r, h.has_utf8_name(), file_name_length));
-        h.extra_field = try_io!(r.read_exact(extra_field_length));
-
-        // check for some things we don't support (yet?)
-        assert!(!h.is_encrypted());
-        assert!(!h.is_compressed_patched_data());
-        assert!(!h.has_data_descriptor());
-        assert!(!h.uses_strong_encryption());
-        assert!(!h.uses_masking());
+    // reads the rest of a LocalFileHeader whose signature has already been consumed,
+    // for readers that can't seek back after looking at it
+    pub fn read_after_signature<T:Read>(r: &mut T) -> ZipResult<LocalFileHeader> {
+        let mut h = LocalFileHeader::new();
 
+        h.version_needed_to_extract = try!(r.read_le_u16());
+        h.general_purpose_bit_flag = try!(r.read_le_u16());
+        h.compression_method = try!(r.read_le_u16());
+        h.last_modified_datetime = try!(MsdosDateTime::read(r));
+        h.crc32 = try!(r.read_le_u32());
+        h.compressed_size = try!(r.read_le_u32());
+        h.uncompressed_size = try!(r.read_le_u32());
+        let file_name_length = try!(r.read_le_u16()) as usize;
+        let extra_field_length = try!(r.read_le_u16()) as usize;
+        h.file_name = try!(read_maybe_utf8(r, h.has_utf8_name(), file_name_length));
+        h.extra_field = try!(r.read_vec(extra_field_length));
         Ok(h)
     }
 
-    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
-        try_io!(w.write_le_u32(LFH_SIGNATURE));
-        try_io!(w.write_le_u16(self.version_needed_to_extract));
-        try_io!(w.write_le_u16(self.general_purpose_bit_flag));
-        try_io!(w.write_le_u16(self.compression_method));
-        try_io!(self.last_modified_datetime.write(w));
-        try_io!(w.write_le_u32(self.crc32));
-        try_io!(w.write_le_u32(self.compressed_size));
-        try_io!(w.write_le_u32(self.uncompressed_size));
-        try_io!(w.write_le_u16(try!(ensure_u16_field_length(self.file_name.len()))));
-        try_io!(w.write_le_u16(try!(ensure_u16_field_length(self.extra_field.len()))));
+    // checks for some things we don't support (yet?) before the data is extracted; headers
+    // with them can still be read, so that their entries can be listed or copied raw
+    pub fn check_supported(&self) -> ZipResult<()> {
+        // the only encryption we understand is WinZip AES, which has its own method code
+        if (self.is_encrypted() && self.compression_method != AES_COMPRESSION_METHOD) ||
+           self.uses_strong_encryption() || self.uses_masking() {
+            return Err(ZipError::UnsupportedEncryption);
+        }
+        if self.is_compressed_patched_data() {
+            return Err(ZipError::UnsupportedCompressionMethod(self.compression_method));
+        }
+        Ok(())
+    }
+
+    pub fn write<T:Write>(&self, w: &mut T) -> ZipResult<()> {
+        try!(w.write_le_u32(LFH_SIGNATURE));
+        try!(w.write_le_u16(self.version_needed_to_extract));
+        try!(w.write_le_u16(self.general_purpose_bit_flag));
+        try!(w.write_le_u16(self.compression_method));
+        try!(self.last_modified_datetime.write(w));
+        try!(w.write_le_u32(self.crc32));
+        try!(w.write_le_u32(self.compressed_size));
+        try!(w.write_le_u32(self.uncompressed_size));
+        try!(w.write_le_u16(try!(ensure_u16_field_length(self.file_name.len()))));
+        try!(w.write_le_u16(try!(ensure_u16_field_length(self.extra_field.len()))));
         try!(write_maybe_utf8(w, self.has_utf8_name(), self.file_name.to_slice()));
-        try_io!(w.write_all(&self.extra_field));
+        try!(w.write_all(&self.extra_field));
         Ok(())
     }
 
@@ -245,16 +359,60 @@ impl LocalFileHeader {
     }
 }
 
-// TODO: Add support for data descriptor section after the file contents (typically used when the zip file
-// writer doesn't know the file size beforehand, because it's receiving a stream of data or something)
+// The data descriptor follows the file contents when bit 3 of the flags is set (typically used when the
+// zip file writer doesn't know the file size beforehand, because it's receiving a stream of data or something)
 
 pub static DD_SIGNATURE: u32 = 0x08074b50;
 
 pub struct DataDescriptor {
     pub signature_present: bool, // not standard but sometimes present
     pub crc32: u32,
-    pub compressed_size: u32,
-    pub uncompressed_size: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+impl DataDescriptor {
+    // reads a DataDescriptor from the current position of the reader r;
+    // Zip64 entries (with a Zip64 extended information extra field) have 8-byte sizes
+    pub fn read<T:Read>(r: &mut T, zip64: bool) -> ZipResult<DataDescriptor> {
+        let first = try!(r.read_le_u32());
+        let (signature_present, crc32) = if first == DD_SIGNATURE {
+            (true, try!(r.read_le_u32()))
+        } else {
+            (false, first)
+        };
+        let (compressed_size, uncompressed_size) = if zip64 {
+            (try!(r.read_le_u64()), try!(r.read_le_u64()))
+        } else {
+            (try!(r.read_le_u32()) as u64, try!(r.read_le_u32()) as u64)
+        };
+        Ok(DataDescriptor {
+            signature_present: signature_present,
+            crc32: crc32,
+            compressed_size: compressed_size,
+            uncompressed_size: uncompressed_size,
+        })
+    }
+
+    // the signature is written if signature_present is set; without zip64, sizes that
+    // don't fit in 4 bytes are an error
+    pub fn write<T:Write>(&self, w: &mut T, zip64: bool) -> ZipResult<()> {
+        if self.signature_present {
+            try!(w.write_le_u32(DD_SIGNATURE));
+        }
+        try!(w.write_le_u32(self.crc32));
+        if zip64 {
+            try!(w.write_le_u64(self.compressed_size));
+            try!(w.write_le_u64(self.uncompressed_size));
+        } else {
+            if self.compressed_size > 0xffffffff || self.uncompressed_size > 0xffffffff {
+                return Err(ZipError::TooLongField);
+            }
+            try!(w.write_le_u32(self.compressed_size as u32));
+            try!(w.write_le_u32(self.uncompressed_size as u32));
+        }
+        Ok(())
+    }
 }
 
 // ==== CENTRAL DIRECTORY HEADER ====
@@ -287,12 +445,35 @@ impl CentralDirectoryHeader {
     pub fn has_utf8_name(&self) -> bool              { (self.general_purpose_bit_flag & 2048) != 0 }
     pub fn uses_masking(&self) -> bool               { (self.general_purpose_bit_flag & 8192) != 0 }
 
-    pub fn total_size(&self) -> usize {
+    // the name from a valid Unicode Path extra field, which takes precedence
+    pub fn unicode_path(&self) -> Option<String> {
+        parse_unicode_path_extra(&self.extra_field, self.file_name.as_bytes())
+    }
+
+    // returns (uncompressed size, compressed size, local header offset) with the fields set
+    // to 0xFFFFFFFF taken from the Zip64 extra field, which lists them in that order; a field
+    // the extra field doesn't have keeps the value of the header
+    pub fn zip64_fields(&self) -> (u64, u64, u64) {
+        let mut data = find_extra_field(&self.extra_field, ZIP64_EXTRA_FIELD_ID).unwrap_or(&[]);
+        let mut field = |value: u32| {
+            if value != 0xffffffff {
+                return value as u64;
+            }
+            data.read_le_u64().unwrap_or(value as u64)
+        };
+        let uncompressed = field(self.uncompressed_size);
+        let compressed = field(self.compressed_size);
+        let offset = field(self.relative_offset_of_local_header);
+        (uncompressed, compressed, offset)
+    }
+
+    // as LocalFileHeader::total_size
+    pub fn total_size(&self) -> u64 {
         let central_directory_header_fixed_size = 46;
         central_directory_header_fixed_size
-            + self.file_name.len()
-            + self.extra_field.len()
-            + self.file_comment.len()
+            + self.file_name.len() as u64
+            + self.extra_field.len() as u64
+            + self.file_comment.len() as u64
     }
 
     pub fn new() -> CentralDirectoryHeader {
@@ -316,31 +497,31 @@ impl CentralDirectoryHeader {
     }
 
     // reads a CentralDirectoryHeader from the current position of the reader r
-    pub fn read<T:Reader>(r: &mut T) -> ZipResult<CentralDirectoryHeader> {
+    pub fn read<T:Read>(r: &mut T) -> ZipResult<CentralDirectoryHeader> {
         let mut h = CentralDirectoryHeader::new();
 
-        let magic = try_io!(r.read_le_u32());
+        let magic = try!(r.read_le_u32());
         if magic != CDH_SIGNATURE {
-            return Err(ZipError::InvalidSignature(magic));
+            return Err(ZipError::InvalidSignature(magic, None));
         }
 
-        h.version_made_by = try_io!(r.read_le_u16());
-        h.version_needed_to_extract = try_io!(r.read_le_u16());
-        h.general_purpose_bit_flag = try_io!(r.read_le_u16());
-        h.compression_method = try_io!(r.read_le_u16());
-        h.last_modified_datetime = try_io!(MsdosDateTime::read(r));
-        h.crc32 = try_io!(r.read_le_u32());
-        h.compressed_size = try_io!(r.read_le_u32());
-        h.uncompressed_size = try_io!(r.read_le_u32());
-        let file_name_length = try_io!(r.read_le_u16()) as usize;
-        let extra_field_length = try_io!(r.read_le_u16()) as usize;
-        let file_comment_length = try_io!(r.read_le_u16()) as usize;
-        h.disk_number_start = try_io!(r.read_le_u16());
-        h.internal_file_attributes = try_io!(r.read_le_u16());
-        h.external_file_attributes = try_io!(r.read_le_u32());
-        h.relative_offset_of_local_header = try_io!(r.read_le_u32());
+        h.version_made_by = try!(r.read_le_u16());
+        h.version_needed_to_extract = try!(r.read_le_u16());
+        h.general_purpose_bit_flag = try!(r.read_le_u16());
+        h.compression_method = try!(r.read_le_u16());
+        h.last_modified_datetime = try!(MsdosDateTime::read(r));
+        h.crc32 = try!(r.read_le_u32());
+        h.compressed_size = try!(r.read_le_u32());
+        h.uncompressed_size = try!(r.read_le_u32());
+        let file_name_length = try!(r.read_le_u16()) as usize;
+        let extra_field_length = try!(r.read_le_u16()) as usize;
+        let file_comment_length = try!(r.read_le_u16()) as usize;
+        h.disk_number_start = try!(r.read_le_u16());
+        h.internal_file_attributes = try!(r.read_le_u16());
+        h.external_file_attributes = try!(r.read_le_u32());
+        h.relative_offset_of_local_header = try!(r.read_le_u32());
         h.file_name = try!(read_maybe_utf8(r, h.has_utf8_name(), file_name_length));
-        h.extra_field = try_io!(r.read_exact(extra_field_length));
+        h.extra_field = try!(r.read_vec(extra_field_length));
         h.file_comment = try!(read_maybe_utf8(r, h.has_utf8_name(), file_comment_length));
 
         // check for some things we don't support (yet?)
@@ -349,25 +530,25 @@ impl CentralDirectoryHeader {
         Ok(h)
     }
 
-    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
-        try_io!(w.write_le_u32(CDH_SIGNATURE));
-        try_io!(w.write_le_u16(self.version_made_by));
-        try_io!(w.write_le_u16(self.version_needed_to_extract));
-        try_io!(w.write_le_u16(self.general_purpose_bit_flag));
-        try_io!(w.write_le_u16(self.compression_method));
-        try_io!(self.last_modified_datetime.write(w));
-        try_io!(w.write_le_u32(self.crc32));
-        try_io!(w.write_le_u32(self.compressed_size));
-        try_io!(w.write_le_u32(self.uncompressed_size));
-        try_io!(w.write_le_u16(try!(ensure_u16_field_length(self.file_name.len()))));
-        try_io!(w.write_le_u16(try!(ensure_u16_field_length(self.extra_field.len()))));
-        try_io!(w.write_le_u16(try!(ensure_u16_field_length(self.file_comment.len()))));
-        try_io!(w.write_le_u16(self.disk_number_start));
-        try_io!(w.write_le_u16(self.internal_file_attributes));
-        try_io!(w.write_le_u32(self.external_file_attributes));
-        try_io!(w.write_le_u32(self.relative_offset_of_local_header));
+    pub fn write<T:Write>(&self, w: &mut T) -> ZipResult<()> {
+        try!(w.write_le_u32(CDH_SIGNATURE));
+        try!(w.write_le_u16(self.version_made_by));
+        try!(w.write_le_u16(self.version_needed_to_extract));
+        try!(w.write_le_u16(self.general_purpose_bit_flag));
+        try!(w.write_le_u16(self.compression_method));
+        try!(self.last_modified_datetime.write(w));
+        try!(w.write_le_u32(self.crc32));
+        try!(w.write_le_u32(self.compressed_size));
+        try!(w.write_le_u32(self.uncompressed_size));
+        try!(w.write_le_u16(try!(ensure_u16_field_length(self.file_name.len()))));
+        try!(w.write_le_u16(try!(ensure_u16_field_length(self.extra_field.len()))));
+        try!(w.write_le_u16(try!(ensure_u16_field_length(self.file_comment.len()))));
+        try!(w.write_le_u16(self.disk_number_start));
+        try!(w.write_le_u16(self.internal_file_attributes));
+        try!(w.write_le_u32(self.external_file_attributes));
+        try!(w.write_le_u32(self.relative_offset_of_local_header));
         try!(write_maybe_utf8(w, self.has_utf8_name(), self.file_name.to_slice()));
-        try_io!(w.write_all(&self.extra_field));
+        try!(w.write_all(&self.extra_field));
         try!(write_maybe_utf8(w, self.has_utf8_name(), self.file_comment.to_slice()));
         Ok(())
     }
@@ -380,11 +561,35 @@ pub struct CentralDirectoryDigitalSignature {
     pub data: Vec<u8>
 }
 
+impl CentralDirectoryDigitalSignature {
+    pub fn read<T:Read>(r: &mut T) -> ZipResult<CentralDirectoryDigitalSignature> {
+        let magic = try!(r.read_le_u32());
+        if magic != CDDS_SIGNATURE {
+            return Err(ZipError::InvalidSignature(magic, None));
+        }
+        let data_size = try!(r.read_le_u16());
+        let data = try!(r.read_vec(data_size as usize));
+        Ok(CentralDirectoryDigitalSignature { data_size: data_size, data: data })
+    }
+
+    pub fn write<T:Write>(&self, w: &mut T) -> ZipResult<()> {
+        try!(w.write_le_u32(CDDS_SIGNATURE));
+        try!(w.write_le_u16(try!(ensure_u16_field_length(self.data.len()))));
+        try!(w.write_all(&self.data));
+        Ok(())
+    }
+}
+
 
 // ==== END OF CENTRAL DIRECTORY RECORD ====
 
 pub static EOCDR_SIGNATURE: u32 = 0x06054b50;
 
+// the Zip64 end of central directory locator, right before the end record of Zip64 archives
+pub static ZIP64_EOCDL_SIGNATURE: u32 = 0x07064b50;
+pub static ZIP64_EOCDL_SIZE: u64 = 20;
+
+#[derive(Clone)]
 pub struct EndOfCentralDirectoryRecord {
     pub disk_number: u16,
     pub disk_number_with_start_of_central_directory: u16,
@@ -408,22 +613,22 @@ impl EndOfCentralDirectoryRecord {
         }
     }
 
-    pub fn read<T:Reader>(r: &mut T) -> ZipResult<EndOfCentralDirectoryRecord> {
+    pub fn read<T:Read>(r: &mut T) -> ZipResult<EndOfCentralDirectoryRecord> {
         let mut h = EndOfCentralDirectoryRecord::new();
 
-        let magic = try_io!(r.read_le_u32());
+        let magic = try!(r.read_le_u32());
         if magic != EOCDR_SIGNATURE {
-            return Err(ZipError::InvalidSignature(magic));
+            return Err(ZipError::InvalidSignature(magic, None));
         }
 
-        h.disk_number = try_io!(r.read_le_u16());
-        h.disk_number_with_start_of_central_directory = try_io!(r.read_le_u16());
-        h.entry_count_this_disk = try_io!(r.read_le_u16());
-        h.total_entry_count = try_io!(r.read_le_u16());
-        h.central_directory_size = try_io!(r.read_le_u32());
-        h.central_directory_offset = try_io!(r.read_le_u32());
-        let comment_length = try_io!(r.read_le_u16()) as usize;
-        h.comment = try_io!(r.read_exact(comment_length));
+        h.disk_number = try!(r.read_le_u16());
+        h.disk_number_with_start_of_central_directory = try!(r.read_le_u16());
+        h.entry_count_this_disk = try!(r.read_le_u16());
+        h.total_entry_count = try!(r.read_le_u16());
+        h.central_directory_size = try!(r.read_le_u32());
+        h.central_directory_offset = try!(r.read_le_u32());
+        let comment_length = try!(r.read_le_u16()) as usize;
+        h.comment = try!(r.read_vec(comment_length));
 
         // check for some things we don't support (yet?)
         // TODO
@@ -431,18 +636,321 @@ impl EndOfCentralDirectoryRecord {
         Ok(h)
     }
 
-    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
-        try_io!(w.write_le_u32(EOCDR_SIGNATURE));
-        try_io!(w.write_le_u16(self.disk_number));
-        try_io!(w.write_le_u16(self.disk_number_with_start_of_central_directory));
-        try_io!(w.write_le_u16(self.entry_count_this_disk));
-        try_io!(w.write_le_u16(self.total_entry_count));
-        try_io!(w.write_le_u32(self.central_directory_size));
-        try_io!(w.write_le_u32(self.central_directory_offset));
-        try_io!(w.write_le_u16(try!(ensure_u16_field_length(self.comment.len()))));
-        try_io!(w.write_all(&self.comment));
+    pub fn write<T:Write>(&self, w: &mut T) -> ZipResult<()> {
+        try!(w.write_le_u32(EOCDR_SIGNATURE));
+        try!(w.write_le_u16(self.disk_number));
+        try!(w.write_le_u16(self.disk_number_with_start_of_central_directory));
+        try!(w.write_le_u16(self.entry_count_this_disk));
+        try!(w.write_le_u16(self.total_entry_count));
+        try!(w.write_le_u32(self.central_directory_size));
+        try!(w.write_le_u32(self.central_directory_offset));
+        try!(w.write_le_u16(try!(ensure_u16_field_length(self.comment.len()))));
+        try!(w.write_all(&self.comment));
+        Ok(())
+    }
+
+}
+
+
+// ==== ZIP64 END OF CENTRAL DIRECTORY RECORD AND LOCATOR ====
+
+// These come between the central directory and the end record, which leaves to them the
+// fields that don't fit (set to 0xFFFF or 0xFFFFFFFF there).
+
+pub static ZIP64_EOCDR_SIGNATURE: u32 = 0x06064b50;
+
+#[derive(Clone)]
+pub struct Zip64EndOfCentralDirectoryRecord {
+    pub version_made_by: u16,
+    pub version_needed_to_extract: u16,
+    pub disk_number: u32,
+    pub disk_number_with_start_of_central_directory: u32,
+    pub entry_count_this_disk: u64,
+    pub total_entry_count: u64,
+    pub central_directory_size: u64,
+    pub central_directory_offset: u64,
+    // the zip64 extensible data sector is neither read nor written
+}
+
+impl Zip64EndOfCentralDirectoryRecord {
+    pub fn new() -> Zip64EndOfCentralDirectoryRecord {
+        Zip64EndOfCentralDirectoryRecord {
+            version_made_by: 0,
+            version_needed_to_extract: 45,
+            disk_number: 0,
+            disk_number_with_start_of_central_directory: 0,
+            entry_count_this_disk: 0,
+            total_entry_count: 0,
+            central_directory_size: 0,
+            central_directory_offset: 0,
+        }
+    }
+
+    pub fn read<T:Read>(r: &mut T) -> ZipResult<Zip64EndOfCentralDirectoryRecord> {
+        let magic = try!(r.read_le_u32());
+        if magic != ZIP64_EOCDR_SIGNATURE {
+            return Err(ZipError::InvalidSignature(magic, None));
+        }
+        let size = try!(r.read_le_u64());
+        let mut h = Zip64EndOfCentralDirectoryRecord::new();
+        h.version_made_by = try!(r.read_le_u16());
+        h.version_needed_to_extract = try!(r.read_le_u16());
+        h.disk_number = try!(r.read_le_u32());
+        h.disk_number_with_start_of_central_directory = try!(r.read_le_u32());
+        h.entry_count_this_disk = try!(r.read_le_u64());
+        h.total_entry_count = try!(r.read_le_u64());
+        h.central_directory_size = try!(r.read_le_u64());
+        h.central_directory_offset = try!(r.read_le_u64());
+        // skip the extensible data sector
+        if size > 44 {
+            try!(io::copy(&mut r.take(size - 44), &mut io::sink()));
+        }
+        Ok(h)
+    }
+
+    pub fn write<T:Write>(&self, w: &mut T) -> ZipResult<()> {
+        try!(w.write_le_u32(ZIP64_EOCDR_SIGNATURE));
+        // the size of the rest of the record
+        try!(w.write_le_u64(44));
+        try!(w.write_le_u16(self.version_made_by));
+        try!(w.write_le_u16(self.version_needed_to_extract));
+        try!(w.write_le_u32(self.disk_number));
+        try!(w.write_le_u32(self.disk_number_with_start_of_central_directory));
+        try!(w.write_le_u64(self.entry_count_this_disk));
+        try!(w.write_le_u64(self.total_entry_count));
+        try!(w.write_le_u64(self.central_directory_size));
+        try!(w.write_le_u64(self.central_directory_offset));
+        Ok(())
+    }
+}
+
+pub struct Zip64EndOfCentralDirectoryLocator {
+    pub disk_number_with_zip64_end_record: u32,
+    pub zip64_end_record_offset: u64,
+    pub total_disk_count: u32,
+}
+
+impl Zip64EndOfCentralDirectoryLocator {
+    pub fn new() -> Zip64EndOfCentralDirectoryLocator {
+        Zip64EndOfCentralDirectoryLocator {
+            disk_number_with_zip64_end_record: 0,
+            zip64_end_record_offset: 0,
+            total_disk_count: 1,
+        }
+    }
+
+    pub fn read<T:Read>(r: &mut T) -> ZipResult<Zip64EndOfCentralDirectoryLocator> {
+        let magic = try!(r.read_le_u32());
+        if magic != ZIP64_EOCDL_SIGNATURE {
+            return Err(ZipError::InvalidSignature(magic, None));
+        }
+        Ok(Zip64EndOfCentralDirectoryLocator {
+            disk_number_with_zip64_end_record: try!(r.read_le_u32()),
+            zip64_end_record_offset: try!(r.read_le_u64()),
+            total_disk_count: try!(r.read_le_u32()),
+        })
+    }
+
+    pub fn write<T:Write>(&self, w: &mut T) -> ZipResult<()> {
+        try!(w.write_le_u32(ZIP64_EOCDL_SIGNATURE));
+        try!(w.write_le_u32(self.disk_number_with_zip64_end_record));
+        try!(w.write_le_u64(self.zip64_end_record_offset));
+        try!(w.write_le_u32(self.total_disk_count));
         Ok(())
     }
+}
+
+
+// ==== EXTRA FIELDS ====
 
+//  4.5.1 In order to allow different programs and different types of information to be
+//  stored in the 'extra' field in .ZIP files, the following structure MUST be used for all
+//  programs storing data in this field:
+//
+//  header1+data1 + header2+data2 . . .
+//
+//  Each header MUST consist of a 2-byte Header ID and a 2-byte data size, both little-endian.
+
+fn le_u16(b: &[u8]) -> u16 {
+    (b[0] as u16) | ((b[1] as u16) << 8)
+}
+
+/// An iterator over the `(header ID, data)` records of an extra field.
+/// Iteration stops at the first truncated record.
+pub struct ExtraFields<'a> {
+    extra: &'a [u8],
+}
+
+pub fn extra_fields<'a>(extra: &'a [u8]) -> ExtraFields<'a> {
+    ExtraFields { extra: extra }
 }
 
+impl<'a> Iterator for ExtraFields<'a> {
+    type Item = (u16, &'a [u8]);
+    fn next(&mut self) -> Option<(u16, &'a [u8])> {
+        if self.extra.len() < 4 {
+            return None;
+        }
+        let id = le_u16(self.extra);
+        let size = le_u16(&self.extra[2..]) as usize;
+        if 4 + size > self.extra.len() {
+            self.extra = &[];
+            return None;
+        }
+        let data = &self.extra[4..4+size];
+        self.extra = &self.extra[4+size..];
+        Some((id, data))
+    }
+}
+
+/// Looks for the extra field record with the given header ID and returns its data.
+/// A truncated record is treated as absent.
+pub fn find_extra_field<'a>(extra: &'a [u8], header_id: u16) -> Option<&'a [u8]> {
+    extra_fields(extra).find(|&(id, _)| id == header_id).map(|(_, data)| data)
+}
+
+// Zip64 extended information, see section 4.5.3 of APPNOTE.TXT
+pub static ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+
+/// Builds a Zip64 extended information extra field holding `values`, which are the sizes
+/// and offset whose header fields are set to 0xFFFFFFFF, in the order of APPNOTE.TXT
+/// (uncompressed size, compressed size, local header offset).
+pub fn zip64_extra_field(values: &[u64]) -> Vec<u8> {
+    let mut extra = Vec::with_capacity(4 + 8 * values.len());
+    // writing to a Vec can't fail
+    extra.write_le_u16(ZIP64_EXTRA_FIELD_ID).unwrap();
+    extra.write_le_u16(8 * values.len() as u16).unwrap();
+    for &value in values.iter() {
+        extra.write_le_u64(value).unwrap();
+    }
+    extra
+}
+
+// WinZip AES encryption, see http://www.winzip.com/aes_info.htm
+pub static AES_COMPRESSION_METHOD: u16 = 99;
+pub static AES_EXTRA_FIELD_ID: u16 = 0x9901;
+
+/// AES key size used for an encrypted entry.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum AesStrength {
+    Aes128 = 1,
+    Aes192 = 2,
+    Aes256 = 3,
+}
+
+impl AesStrength {
+    pub fn from_u8(x: u8) -> Option<AesStrength> {
+        match x {
+            1 => Some(AesStrength::Aes128),
+            2 => Some(AesStrength::Aes192),
+            3 => Some(AesStrength::Aes256),
+            _ => None
+        }
+    }
+
+    pub fn key_length(&self) -> usize {
+        match *self {
+            AesStrength::Aes128 => 16,
+            AesStrength::Aes192 => 24,
+            AesStrength::Aes256 => 32,
+        }
+    }
+
+    pub fn salt_length(&self) -> usize { self.key_length() / 2 }
+}
+
+/// The AE-x extra field (0x9901) that accompanies entries using compression method 99.
+pub struct AesExtraField {
+    pub vendor_version: u16, // 1 for AE-1, 2 for AE-2 (which leaves crc32 unset)
+    pub strength: AesStrength,
+    pub compression_method: u16, // the method actually used before encryption
+}
+
+impl AesExtraField {
+    pub fn from_extra_field(extra: &[u8]) -> Option<AesExtraField> {
+        let data = match find_extra_field(extra, AES_EXTRA_FIELD_ID) {
+            Some(data) if data.len() >= 7 && &data[2..4] == b"AE" => data,
+            _ => return None
+        };
+        match AesStrength::from_u8(data[4]) {
+            Some(strength) => Some(AesExtraField {
+                vendor_version: le_u16(&data[0..]),
+                strength: strength,
+                compression_method: le_u16(&data[5..]),
+            }),
+            None => None
+        }
+    }
+}
+
+// Info-ZIP Unicode Path extra field, see proginfo/extrafld.txt in the Info-ZIP distribution
+pub static UNICODE_PATH_EXTRA_FIELD_ID: u16 = 0x7075;
+
+/// Returns the UTF-8 name from an Info-ZIP Unicode Path extra field (0x7075), which archivers
+/// add next to a name in a legacy encoding. The field records the crc32 of the name it was
+/// made for, and is ignored if that isn't `legacy_name`, as happens when a tool unaware of
+/// the field renames the entry. Unknown versions and invalid UTF-8 are ignored as well.
+pub fn parse_unicode_path_extra(extra: &[u8], legacy_name: &[u8]) -> Option<String> {
+    let data = match find_extra_field(extra, UNICODE_PATH_EXTRA_FIELD_ID) {
+        Some(data) if data.len() >= 5 && data[0] == 1 => data,
+        _ => return None
+    };
+    let name_crc32 = (le_u16(&data[1..]) as u32) | ((le_u16(&data[3..]) as u32) << 16);
+    if name_crc32 != crc32::crc32(legacy_name) {
+        return None;
+    }
+    String::from_utf8(data[5..].to_vec()).ok()
+}
+
+// Info-ZIP "new Unix" extra field, see proginfo/extrafld.txt in the Info-ZIP distribution
+pub static UNIX_EXTRA_FIELD_ID: u16 = 0x7875;
+
+/// The Info-ZIP "new Unix" extra field (0x7875), which starts with a version byte.
+#[derive(PartialEq, Debug, Clone)]
+pub enum UnixExtraField {
+    /// Version 1: the owner's uid and gid.
+    V1 { uid: u64, gid: u64 },
+    /// A version we don't know how to interpret, with the data following the version byte.
+    Unknown { version: u8, data: Vec<u8> },
+}
+
+impl UnixExtraField {
+    /// Returns the first version 1 record of the extra field, or failing that,
+    /// the first record of a version we don't understand.
+    pub fn from_extra_field(extra: &[u8]) -> Option<UnixExtraField> {
+        let mut unknown = None;
+        for (id, data) in extra_fields(extra) {
+            if id != UNIX_EXTRA_FIELD_ID || data.len() == 0 {
+                continue;
+            }
+            if data[0] != 1 {
+                if unknown.is_none() {
+                    unknown = Some(UnixExtraField::Unknown { version: data[0],
+                                                             data: data[1..].to_vec() });
+                }
+                continue;
+            }
+            // version 1 is: UIDSize, UID, GIDSize, GID (all variable-length little-endian)
+            let (uid, rest) = match read_var_le(&data[1..]) { Some(v) => v, None => continue };
+            let (gid, _) = match read_var_le(rest) { Some(v) => v, None => continue };
+            return Some(UnixExtraField::V1 { uid: uid, gid: gid });
+        }
+        unknown
+    }
+}
+
+// reads a size byte followed by that many bytes of a little-endian integer
+fn read_var_le(data: &[u8]) -> Option<(u64, &[u8])> {
+    if data.len() == 0 {
+        return None;
+    }
+    let size = data[0] as usize;
+    if size > 8 || data.len() < 1 + size {
+        return None;
+    }
+    let mut value = 0u64;
+    for (i, &b) in data[1..1+size].iter().enumerate() {
+        value |= (b as u64) << (8 * i);
+    }
+    Some((value, &data[1+size..]))
+}
@@ -0,0 +1,342 @@
+//! An inflater for Deflate (compression method 8) and Deflate64, PKWARE's "enhanced deflate"
+//! (method 9, with the `deflate64` feature). Deflate64 is Deflate with a 64 KB window: length
+//! code 285 takes 16 extra bits instead of meaning 258, and distance codes 30 and 31 reach
+//! back up to 65536 bytes. The compressed stream has to be in memory, but the output is
+//! handed on to a writer as it is inflated, keeping no more of it than matches reach back to.
+
+// the base length and extra bits of length codes 257 to 285, with 285 as Deflate64 has it
+static LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 3];
+static LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 16];
+
+// the base distance and extra bits of distance codes 0 to 31
+static DISTANCE_BASE: [u32; 32] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+    32769, 49153];
+static DISTANCE_EXTRA: [u8; 32] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 14, 14];
+
+use std::io::{self, Write};
+
+// how far back matches reach, with Deflate64; Deflate's 32 KB window fits in it
+static WINDOW_SIZE: usize = 64 * 1024;
+
+// how much output is held before all of it but the window is written out
+static FLUSH_SIZE: usize = 2 * 64 * 1024;
+
+// how much is inflated between two calls of the cancellation callback
+static CANCEL_INTERVAL: usize = 64 * 1024;
+
+// the order code length code lengths come in, in a dynamic block header
+static CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Why inflating stopped short. What was inflated before an invalid or truncated stream
+/// has been written out; a cancelled one or a failed write may leave some of it unwritten.
+pub enum InflateError {
+    Invalid,
+    Cancelled,
+    Write(io::Error),
+}
+
+impl From<&'static str> for InflateError {
+    fn from(_: &'static str) -> InflateError {
+        InflateError::Invalid
+    }
+}
+
+/// Inflates a raw Deflate stream, or a Deflate64 one if `deflate64` is set, into `writer`,
+/// returning the number of bytes inflated. Inflating stops once the output is past `limit`
+/// bytes, so callers checking the size against the limit find it exceeded without the whole
+/// stream being inflated, and callers wanting only the start of the output don't inflate the
+/// rest; the bytes past `limit` are still written. `cancel` is called every 64 KB of output,
+/// and inflating stops with an error once it returns `true`.
+pub fn inflate(data: &[u8], deflate64: bool, limit: u64, writer: &mut Write,
+               cancel: &mut FnMut() -> bool) -> Result<u64, InflateError> {
+    let mut inflater = Inflater { deflate64: deflate64, limit: limit, cancel: cancel, checked: 0,
+                                  writer: writer, out: Vec::new(), flushed: 0 };
+    let result = inflater.inflate_all(data);
+    match result {
+        Ok(()) | Err(InflateError::Invalid) => try!(inflater.flush(0)),
+        Err(_) => {}
+    }
+    result.map(|()| inflater.total())
+}
+
+struct Inflater<'a> {
+    deflate64: bool,
+    limit: u64,
+    cancel: &'a mut FnMut() -> bool,
+    // the output length at the last call of `cancel`
+    checked: u64,
+    writer: &'a mut Write,
+    // the output not written yet, which starts with the window once there is one
+    out: Vec<u8>,
+    // the length of the output written, which `out` follows
+    flushed: u64,
+}
+
+impl<'a> Inflater<'a> {
+    fn inflate_all(&mut self, data: &[u8]) -> Result<(), InflateError> {
+        let mut bits = Bits { data: data, pos: 0, buf: 0, count: 0 };
+        loop {
+            let last = try!(bits.take(1)) == 1;
+            match try!(bits.take(2)) {
+                0 => try!(stored_block(&mut bits, &mut self.out)),
+                1 => {
+                    let (lengths, distances) = fixed_codes();
+                    try!(self.codes(&mut bits, &lengths, &distances));
+                }
+                2 => {
+                    let (lengths, distances) = try!(dynamic_codes(&mut bits));
+                    try!(self.codes(&mut bits, &lengths, &distances));
+                }
+                _ => return Err(InflateError::Invalid)
+            }
+            if last || self.total() > self.limit {
+                return Ok(());
+            }
+            try!(self.checkpoint());
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.flushed + self.out.len() as u64
+    }
+
+    // calls `cancel` and writes out the output before the window, now and then
+    fn checkpoint(&mut self) -> Result<(), InflateError> {
+        if self.total() - self.checked >= CANCEL_INTERVAL as u64 {
+            self.checked = self.total();
+            if (self.cancel)() {
+                return Err(InflateError::Cancelled);
+            }
+        }
+        if self.out.len() >= FLUSH_SIZE {
+            try!(self.flush(WINDOW_SIZE));
+        }
+        Ok(())
+    }
+
+    // writes out all of the output but the last `keep` bytes
+    fn flush(&mut self, keep: usize) -> Result<(), InflateError> {
+        if self.out.len() <= keep {
+            return Ok(());
+        }
+        let n = self.out.len() - keep;
+        if let Err(e) = self.writer.write_all(&self.out[..n]) {
+            return Err(InflateError::Write(e));
+        }
+        self.out.drain(..n);
+        self.flushed += n as u64;
+        Ok(())
+    }
+
+    fn codes(&mut self, bits: &mut Bits, lengths: &Huffman,
+             distances: &Huffman) -> Result<(), InflateError> {
+        loop {
+            let symbol = try!(lengths.decode(bits)) as usize;
+            if symbol < 256 {
+                self.out.push(symbol as u8);
+            } else if symbol == 256 {
+                return Ok(());
+            } else {
+                try!(self.copy_match(bits, symbol - 257, distances));
+            }
+            if self.total() > self.limit {
+                return Ok(());
+            }
+            try!(self.checkpoint());
+        }
+    }
+
+    // copies the match of length code `code`, whose distance follows it; the window keeps
+    // at least as much output as the longest distance, so matches are always in `out`
+    fn copy_match(&mut self, bits: &mut Bits, code: usize,
+                  distances: &Huffman) -> Result<(), &'static str> {
+        if code >= LENGTH_BASE.len() {
+            return Err("invalid length code");
+        }
+        let len = if code == 28 && !self.deflate64 {
+            258
+        } else {
+            LENGTH_BASE[code] as usize + try!(bits.take(LENGTH_EXTRA[code] as u32)) as usize
+        };
+        let code = try!(distances.decode(bits)) as usize;
+        // Deflate's window ends at 32768 bytes, before distance codes 30 and 31
+        if code >= DISTANCE_BASE.len() || (code >= 30 && !self.deflate64) {
+            return Err("invalid distance code");
+        }
+        let distance = DISTANCE_BASE[code] as usize + try!(bits.take(DISTANCE_EXTRA[code] as u32)) as usize;
+        if distance > self.out.len() {
+            return Err("distance past the start of the output");
+        }
+        // the match may overlap the bytes it produces, so copy a byte at a time
+        let start = self.out.len() - distance;
+        for i in 0..len {
+            let b = self.out[start + i];
+            self.out.push(b);
+        }
+        Ok(())
+    }
+}
+
+// reads bits from the least significant one of each byte on, as Deflate packs them
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u64,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn take(&mut self, n: u32) -> Result<u32, &'static str> {
+        while self.count < n {
+            if self.pos >= self.data.len() {
+                return Err("truncated stream");
+            }
+            self.buf |= (self.data[self.pos] as u64) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = (self.buf & ((1 << n) - 1)) as u32;
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // drops the bits left of the current byte, which are fewer than 8
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+// a canonical Huffman code, as the number of codes of each length and the symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, &'static str> {
+        let mut counts = [0u16; 16];
+        for &len in lengths.iter() {
+            counts[len as usize] += 1;
+        }
+        // each length doubles the codes available; more codes than that can't be decoded
+        let mut left = 1i32;
+        for len in 1..16 {
+            left = (left << 1) - counts[len] as i32;
+            if left < 0 {
+                return Err("over-subscribed Huffman code");
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Huffman { counts: counts, symbols: symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, &'static str> {
+        // codes of a length are consecutive, and follow the shorter codes shifted left
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= try!(bits.take(1)) as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code")
+    }
+}
+
+fn stored_block(bits: &mut Bits, out: &mut Vec<u8>) -> Result<(), &'static str> {
+    bits.align();
+    let len = try!(bits.take(16));
+    let nlen = try!(bits.take(16));
+    if len != !nlen & 0xffff {
+        return Err("stored block length doesn't match its complement");
+    }
+    let start = bits.pos;
+    let end = start + len as usize;
+    if end > bits.data.len() {
+        // what there is of the block is still output
+        out.extend_from_slice(&bits.data[start..]);
+        return Err("truncated stream");
+    }
+    out.extend_from_slice(&bits.data[start..end]);
+    bits.pos = end;
+    Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0...143 => 8,
+            144...255 => 9,
+            256...279 => 7,
+            _ => 8
+        };
+    }
+    // the fixed codes are complete, so they can't fail to build
+    (Huffman::new(&lengths).unwrap(), Huffman::new(&[5u8; 32]).unwrap())
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), &'static str> {
+    let literal_count = try!(bits.take(5)) as usize + 257;
+    let distance_count = try!(bits.take(5)) as usize + 1;
+    let code_length_count = try!(bits.take(4)) as usize + 4;
+    if literal_count > 286 {
+        return Err("too many length codes");
+    }
+    let mut code_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER[..code_length_count].iter() {
+        code_lengths[i] = try!(bits.take(3)) as u8;
+    }
+    let code_length_code = try!(Huffman::new(&code_lengths));
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = try!(code_length_code.decode(bits));
+        let (len, repeat) = match symbol {
+            0...15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&previous) => (previous, 3 + try!(bits.take(2))),
+                None => return Err("repeated code length without a previous one")
+            },
+            17 => (0, 3 + try!(bits.take(3))),
+            _ => (0, 11 + try!(bits.take(7)))
+        };
+        if lengths.len() + repeat as usize > literal_count + distance_count {
+            return Err("code lengths run past the codes");
+        }
+        for _ in 0..repeat {
+            lengths.push(len);
+        }
+    }
+    if lengths[256] == 0 {
+        return Err("no end of block code");
+    }
+    let literal_code = try!(Huffman::new(&lengths[..literal_count]));
+    let distance_code = try!(Huffman::new(&lengths[literal_count..]));
+    Ok((literal_code, distance_code))
+}
@@ -1,14 +1,34 @@
-#![feature(core, old_io, old_path, collections, rustc_private)]
+#![feature(core, collections, rustc_private)]
 
+extern crate crypto;
 extern crate flate;
 extern crate maybe_utf8;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+#[cfg(feature = "mmap")]
+extern crate memmap;
 
-pub use self::fileinfo::{CompressionMethod, FileInfo};
-pub use self::reader::ZipReader;
+pub use self::fileinfo::{CompressionMethod, FileInfo, HostOs};
+pub use self::reader::{ZipReader, ZipReaderOptions, StreamingZipReader};
+pub use self::writer::{ZipWriter, FileOptions};
+pub use self::names::{MaybeUtf8Ext, SplitBytes};
+#[cfg(feature = "mmap")]
+pub use self::mmap::{MmapZipReader, MmapSource};
 
+mod aes;
+mod cp437;
 mod crc32;
-#[macro_use] pub mod error;
+mod inflate;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod names;
+mod util;
+pub mod error;
 pub mod format;
 pub mod fileinfo;
 pub mod reader;
+pub mod writer;
+
+#[cfg(test)]
+mod test;
 
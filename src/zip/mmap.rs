@@ -0,0 +1,74 @@
+//! Reading archives through a memory map, with the `mmap` feature.
+
+use std::cmp;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use memmap::{Mmap, Protection};
+use error::ZipError;
+use reader::ZipReader;
+
+/// A `ZipReader` over a memory-mapped file, see `ZipReader::open_mmap`.
+pub type MmapZipReader = ZipReader<MmapSource>;
+
+/// A seekable reader over a memory-mapped file, like a `Cursor` over a slice: reads copy
+/// out of the mapping and seeking only moves the position.
+///
+/// The mapping is owned by the source, and so lives exactly as long as the reader built on
+/// it. The file itself must not be truncated or written to while it is mapped, as accessing
+/// pages that are gone from the file crashes the process (with `SIGBUS` on Unix).
+pub struct MmapSource {
+    map: Mmap,
+    pos: u64,
+}
+
+impl MmapSource {
+    pub fn open(path: &Path) -> io::Result<MmapSource> {
+        let map = try!(Mmap::open_path(path, Protection::Read));
+        Ok(MmapSource { map: map, pos: 0 })
+    }
+
+    fn slice(&self) -> &[u8] {
+        // the mapping is read-only; the file has to stay as it is, see above
+        unsafe { self.map.as_slice() }
+    }
+}
+
+impl Read for MmapSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let bytes = self.slice();
+            let start = cmp::min(self.pos, bytes.len() as u64) as usize;
+            let n = cmp::min(buf.len(), bytes.len() - start);
+            buf[..n].copy_from_slice(&bytes[start..start + n]);
+            n
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for MmapSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => { self.pos = n; return Ok(n); }
+            SeekFrom::End(n) => (self.map.len() as u64, n),
+            SeekFrom::Current(n) => (self.pos, n)
+        };
+        let new_pos = base as i64 + offset;
+        if new_pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "invalid seek to a negative position"));
+        }
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+}
+
+impl ZipReader<MmapSource> {
+    /// Opens an archive through a memory map instead of reading the file, which makes
+    /// seeking around very large archives cheap. See `MmapSource` for the caveats.
+    pub fn open_mmap(path: &Path) -> Result<MmapZipReader, ZipError> {
+        ZipReader::new(try!(MmapSource::open(path)))
+    }
+}
@@ -0,0 +1,64 @@
+//! Byte-level helpers for entry names, which may or may not be UTF-8.
+
+use maybe_utf8::{MaybeUtf8Buf, MaybeUtf8Slice};
+
+/// Compares names on their bytes, whether they are UTF-8 or raw bytes, so that callers
+/// don't have to go through `as_bytes` for prefix filtering or spotting directory entries.
+pub trait MaybeUtf8Ext {
+    fn starts_with(&self, prefix: &[u8]) -> bool;
+    fn ends_with(&self, suffix: &[u8]) -> bool;
+    /// Takes `c` encoded as UTF-8, so `ends_with_char('/')` is true of raw names too.
+    fn ends_with_char(&self, c: char) -> bool;
+    /// Splits the name at each `sep` byte like `[u8]::split` does, so a trailing separator
+    /// gives an empty last segment; the segments are borrowed, nothing is converted.
+    fn split_bytes<'a>(&'a self, sep: u8) -> SplitBytes<'a>;
+}
+
+/// The segments of a name, from `MaybeUtf8Ext::split_bytes`.
+pub struct SplitBytes<'a> {
+    rest: Option<&'a [u8]>,
+    sep: u8,
+}
+
+impl<'a> Iterator for SplitBytes<'a> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let rest = match self.rest {
+            Some(rest) => rest,
+            None => return None
+        };
+        match rest.iter().position(|&b| b == self.sep) {
+            Some(pos) => {
+                self.rest = Some(&rest[pos + 1..]);
+                Some(&rest[..pos])
+            }
+            None => {
+                self.rest = None;
+                Some(rest)
+            }
+        }
+    }
+}
+
+impl MaybeUtf8Ext for MaybeUtf8Buf {
+    fn starts_with(&self, prefix: &[u8]) -> bool { self.as_bytes().starts_with(prefix) }
+    fn ends_with(&self, suffix: &[u8]) -> bool { self.as_bytes().ends_with(suffix) }
+    fn ends_with_char(&self, c: char) -> bool { ends_with_char(self.as_bytes(), c) }
+    fn split_bytes<'a>(&'a self, sep: u8) -> SplitBytes<'a> {
+        SplitBytes { rest: Some(self.as_bytes()), sep: sep }
+    }
+}
+
+impl<'b> MaybeUtf8Ext for MaybeUtf8Slice<'b> {
+    fn starts_with(&self, prefix: &[u8]) -> bool { self.as_bytes().starts_with(prefix) }
+    fn ends_with(&self, suffix: &[u8]) -> bool { self.as_bytes().ends_with(suffix) }
+    fn ends_with_char(&self, c: char) -> bool { ends_with_char(self.as_bytes(), c) }
+    fn split_bytes<'a>(&'a self, sep: u8) -> SplitBytes<'a> {
+        SplitBytes { rest: Some(self.as_bytes()), sep: sep }
+    }
+}
+
+fn ends_with_char(bytes: &[u8], c: char) -> bool {
+    let mut buf = [0u8; 4];
+    bytes.ends_with(c.encode_utf8(&mut buf).as_bytes())
+}
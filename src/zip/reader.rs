@@ -1,181 +1,1825 @@
 
-use std::old_io::File;
-use std::old_io::{Reader, Writer, Seek, SeekSet, SeekEnd};
-use std::iter::range_inclusive;
+use std::io;
+use std::io::{Cursor, BufReader, Read, Write, BufRead, Seek, SeekFrom};
+use std::fs::{self, File};
+use std::cmp;
+use std::slice;
+use std::str;
+use std::sync::Arc;
+use std::panic;
+use std::thread;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use util::ReadExt;
 use error::ZipError;
-use maybe_utf8::{MaybeUtf8Slice, MaybeUtf8Buf, IntoMaybeUtf8};
-use flate;
+use maybe_utf8::MaybeUtf8Buf;
+use names::MaybeUtf8Ext;
+#[cfg(feature = "zstd")]
+use zstd;
+use aes;
+use cp437;
 use crc32;
+use inflate;
 use format;
 use fileinfo::{CompressionMethod, FileInfo};
 
+// the highest version_needed_to_extract we can handle: 5.1 for AES encryption,
+// or 6.3 when built with zstd
+#[cfg(not(feature = "zstd"))]
+static SUPPORTED_VERSION: u16 = 51;
+#[cfg(feature = "zstd")]
+static SUPPORTED_VERSION: u16 = 63;
+
 pub struct ZipReader<R> {
     reader: R,
     end_record: format::EndOfCentralDirectoryRecord,
+    archive_size: u64,
+    // the size of anything prepended to the archive, like a self-extractor
+    archive_start: u32,
+    // the Zip64 end record, if a locator for it precedes the end record; its directory
+    // offset is made absolute
+    zip64_record: Option<format::Zip64EndOfCentralDirectoryRecord>,
+    options: ZipReaderOptions,
+    // the parsed central directory, filled on first use
+    file_cache: Option<Vec<FileInfo>>,
+    // where the data of an entry starts, by local header offset
+    data_offsets: HashMap<u64, u64>,
+    // where the central directory header of an entry is, by local header offset; filled
+    // as the directory is walked
+    header_offsets: HashMap<u64, u64>,
+}
+
+/// Settings for `ZipReader::with_options`, built up from the defaults of `ZipReader::new`,
+/// e.g. `ZipReaderOptions::new().strict(true).max_decompressed_size(1 << 30)`.
+#[derive(Clone)]
+pub struct ZipReaderOptions {
+    password: Option<Vec<u8>>,
+    strict: bool,
+    max_decompressed_size: Option<u64>,
+    detect_name_encoding: bool,
+    name_decoder: Option<Arc<Fn(&[u8]) -> String + Send + Sync>>,
+    verify_crc: bool,
+    treat_unknown_as_store: bool,
+    keep_partial_output: bool,
+}
+
+impl ZipReaderOptions {
+    pub fn new() -> ZipReaderOptions {
+        ZipReaderOptions { password: None, strict: false, max_decompressed_size: None,
+                           detect_name_encoding: false, name_decoder: None, verify_crc: true,
+                           treat_unknown_as_store: false, keep_partial_output: false }
+    }
+
+    /// See `ZipReader::set_strict`.
+    pub fn strict(mut self, strict: bool) -> ZipReaderOptions {
+        self.strict = strict;
+        self
+    }
+
+    /// See `ZipReader::set_max_decompressed_size`.
+    pub fn max_decompressed_size(mut self, limit: u64) -> ZipReaderOptions {
+        self.max_decompressed_size = Some(limit);
+        self
+    }
+
+    /// See `ZipReader::set_password`.
+    pub fn password(mut self, password: &[u8]) -> ZipReaderOptions {
+        self.password = Some(password.to_vec());
+        self
+    }
+
+    /// Many archivers store UTF-8 names without setting the UTF-8 flag. With this set,
+    /// names without the flag are taken as UTF-8 when they are valid UTF-8, and decoded
+    /// from code page 437 as the specification says otherwise. Off by default, which leaves
+    /// such names as raw bytes.
+    pub fn detect_name_encoding(mut self, detect: bool) -> ZipReaderOptions {
+        self.detect_name_encoding = detect;
+        self
+    }
+
+    /// Decodes names without the UTF-8 flag with `decoder`, for archives known to come from
+    /// a system using Shift-JIS, GBK or the like. Takes precedence over `detect_name_encoding`.
+    pub fn name_decoder<F>(mut self, decoder: F) -> ZipReaderOptions
+            where F: Fn(&[u8]) -> String + Send + Sync + 'static {
+        self.name_decoder = Some(Arc::new(decoder));
+        self
+    }
+
+    /// With this off, the crc32 of extracted entries isn't computed, which saves time on
+    /// archives that are trusted anyway; corrupt data then goes unnoticed. `verify` still
+    /// checks it. On by default.
+    pub fn verify_crc(mut self, verify: bool) -> ZipReaderOptions {
+        self.verify_crc = verify;
+        self
+    }
+
+    /// Extracts entries with a compression method this crate doesn't know as if they were
+    /// stored, for archivers that tag uncompressed data with a method code of their own.
+    /// Beware that entries really compressed with such a method come out as garbage; the
+    /// crc32 check will usually catch that, unless `verify_crc` is off. Off by default.
+    pub fn treat_unknown_as_store(mut self, treat: bool) -> ZipReaderOptions {
+        self.treat_unknown_as_store = treat;
+        self
+    }
+
+    /// With this set, a Deflate or Deflate64 entry whose stream breaks off (e.g. a truncated
+    /// archive) fails with `PartiallyDecompressed`, which carries what was inflated before the
+    /// error, instead of `DecompressionFailure`. Extracting into a writer, as `extract_file`
+    /// does, has already written those contents, so the error's are empty there. Nothing past
+    /// the error is recovered, and the partial contents can't be checked against the crc32.
+    /// Off by default.
+    pub fn keep_partial_output(mut self, keep: bool) -> ZipReaderOptions {
+        self.keep_partial_output = keep;
+        self
+    }
 }
 
 pub struct RawFiles<'a, R:'a> {
     zip_reader: &'a mut ZipReader<R>,
-    current_entry: u16,
+    current_entry: usize,
     current_offset: u64,
 }
 
-impl<'a, R: Reader+Seek> Iterator for RawFiles<'a, R> {
+impl<'a, R> RawFiles<'a, R> {
+    /// Starts over from the first entry of the central directory.
+    pub fn reset(&mut self) {
+        self.current_entry = 0;
+        self.current_offset = self.zip_reader.central_directory_offset();
+    }
+
+    fn stop(&mut self) {
+        self.current_entry = self.zip_reader.entry_count();
+    }
+}
+
+impl<'a, R: Read+Seek> Iterator for RawFiles<'a, R> {
     type Item = Result<FileInfo, ZipError>;
     fn next(&mut self) -> Option<Result<FileInfo, ZipError>> {
-        if self.current_entry < self.zip_reader.end_record.total_entry_count {
-            match self.zip_reader.reader.seek(self.current_offset as i64, SeekSet) {
-                Ok(()) => {}
-                Err(err) => { return Some(Err(ZipError::IoError(err))); }
+        if self.current_entry < self.zip_reader.entry_count() {
+            // the directory can't be walked past a broken header, so stop after reporting it
+            match self.zip_reader.reader.seek(SeekFrom::Start(self.current_offset)) {
+                Ok(_) => {}
+                Err(err) => { self.stop(); return Some(Err(ZipError::IoError(err))); }
             }
             let h = match format::CentralDirectoryHeader::read(&mut self.zip_reader.reader) {
                 Ok(h) => h,
-                Err(err) => { return Some(Err(err)); }
+                Err(err) => { self.stop(); return Some(Err(at_offset(err, self.current_offset))); }
             };
-            let info = FileInfo::from_cdh(&h);
+            let mut info = FileInfo::from_cdh(&h);
+            info.local_file_header_offset += self.zip_reader.archive_start as u64;
+            if !h.has_utf8_name() && h.unicode_path().is_none() {
+                let options = &self.zip_reader.options;
+                match options.name_decoder {
+                    Some(ref decoder) => info.name = MaybeUtf8Buf::from_str(decoder(info.name.as_bytes())),
+                    None if options.detect_name_encoding =>
+                        info.name = detect_name_encoding(info.name.as_bytes()),
+                    None => {}
+                }
+            }
+            self.zip_reader.header_offsets.insert(info.local_file_header_offset, self.current_offset);
             self.current_entry += 1;
-            self.current_offset += h.total_size() as u64;
+            self.current_offset += h.total_size();
             Some(Ok(info))
         } else {
             None
         }
     }
+    // the end record only announces how many entries there are; a header that can't be read
+    // ends the iteration early, so that is an upper bound. `Files` is exact.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.zip_reader.entry_count() - self.current_entry;
+        (0, Some(remaining))
+    }
 }
 
+// iterates over the cached central directory
 pub struct Files<'a, R:'a> {
-    base: RawFiles<'a, R>,
+    zip_reader: &'a ZipReader<R>,
+    index: usize,
 }
 
-impl<'a, R: Reader+Seek> Iterator for Files<'a, R> {
+impl<'a, R> Iterator for Files<'a, R> {
     type Item = FileInfo;
-    fn next(&mut self) -> Option<FileInfo> { self.base.next().map(|i| i.ok().unwrap()) }
-    fn size_hint(&self) -> (usize, Option<usize>) { self.base.size_hint() }
+    fn next(&mut self) -> Option<FileInfo> {
+        let files = self.zip_reader.cached_files();
+        if self.index < files.len() {
+            self.index += 1;
+            Some(files[self.index - 1].clone())
+        } else {
+            None
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.zip_reader.cached_files().len() - self.index;
+        (remaining, Some(remaining))
+    }
 }
 
+impl<'a, R> ExactSizeIterator for Files<'a, R> {}
+
+// as `RawFiles`, yielding only the names and ending where it would yield an error
 pub struct FileNames<'a, R:'a> {
     base: RawFiles<'a, R>,
 }
 
-impl<'a, R: Reader+Seek> Iterator for FileNames<'a, R> {
+impl<'a, R: Read+Seek> Iterator for FileNames<'a, R> {
     type Item = MaybeUtf8Buf;
     fn next(&mut self) -> Option<MaybeUtf8Buf> {
-        self.base.next().map(|i| i.ok().unwrap().name)
+        match self.base.next() {
+            Some(Ok(i)) => Some(i.name),
+            _ => None
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { self.base.size_hint() }
+}
+
+// as `RawFiles`, yielding only the names
+pub struct TryFileNames<'a, R:'a> {
+    base: RawFiles<'a, R>,
+}
+
+impl<'a, R: Read+Seek> Iterator for TryFileNames<'a, R> {
+    type Item = Result<MaybeUtf8Buf, ZipError>;
+    fn next(&mut self) -> Option<Result<MaybeUtf8Buf, ZipError>> {
+        self.base.next().map(|i| i.map(|i| i.name))
     }
     fn size_hint(&self) -> (usize, Option<usize>) { self.base.size_hint() }
 }
 
+// as `Files`, leaving out directory entries
+pub struct FileEntries<'a, R:'a> {
+    base: Files<'a, R>,
+}
+
+impl<'a, R> Iterator for FileEntries<'a, R> {
+    type Item = FileInfo;
+    fn next(&mut self) -> Option<FileInfo> {
+        loop {
+            match self.base.next() {
+                Some(ref info) if is_directory_entry(info) => continue,
+                next => return next
+            }
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { (0, self.base.size_hint().1) }
+}
+
+// borrows the names straight from the cached central directory
+pub struct FileNameBytes<'a> {
+    files: slice::Iter<'a, FileInfo>,
+}
+
+impl<'a> Iterator for FileNameBytes<'a> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<&'a [u8]> {
+        self.files.next().map(|f| f.name.as_bytes())
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { self.files.size_hint() }
+}
+
+impl<'a> ExactSizeIterator for FileNameBytes<'a> {}
+
+// iterates over the cached central directory, extracting each entry
+pub struct Entries<'a, R:'a> {
+    zip_reader: &'a mut ZipReader<R>,
+    index: usize,
+    // an error reading the central directory, yielded first
+    error: Option<ZipError>,
+}
+
+impl<'a, R: Read+Seek> Iterator for Entries<'a, R> {
+    type Item = Result<(FileInfo, Vec<u8>), ZipError>;
+    fn next(&mut self) -> Option<Result<(FileInfo, Vec<u8>), ZipError>> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        let info = match self.zip_reader.cached_files().get(self.index) {
+            Some(info) => info.clone(),
+            None => return None
+        };
+        self.index += 1;
+        if info.is_dir() {
+            return Some(Ok((info, Vec::new())));
+        }
+        Some(self.zip_reader.read(&info, usize::MAX).map(|bytes| (info, bytes)))
+    }
+}
+
+/// A reader over the decompressed contents of an entry, as returned by `ZipReader::open_entry`.
+/// It is buffered, so lines can be read without wrapping it in a `BufReader`.
+pub struct EntryReader {
+    inner: Cursor<Vec<u8>>,
+}
+
+impl EntryReader {
+    /// Reads the rest of the entry as a string like `read_to_string`,
+    /// but drops a leading UTF-8 byte order mark, as written by many Windows tools.
+    pub fn read_to_string_stripping_bom(&mut self) -> io::Result<String> {
+        let mut s = String::new();
+        try!(self.read_to_string(&mut s));
+        if s.starts_with("\u{feff}") {
+            Ok(s["\u{feff}".len()..].to_string())
+        } else {
+            Ok(s)
+        }
+    }
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.inner.read(buf) }
+}
+
+impl BufRead for EntryReader {
+    fn fill_buf<'a>(&'a mut self) -> io::Result<&'a [u8]> { self.inner.fill_buf() }
+    fn consume(&mut self, amt: usize) { self.inner.consume(amt) }
+}
+
+/// Random access to the contents of a stored entry, read straight from the archive;
+/// see `ZipReader::open_entry_seekable`. Positions are relative to the start of the entry.
+/// The crc32 isn't checked, since the entry needn't be read in full.
+pub struct SeekableEntry<'a, R:'a> {
+    reader: &'a mut R,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<'a, R> SeekableEntry<'a, R> {
+    /// The size of the entry.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+impl<'a, R:Read+Seek> Read for SeekableEntry<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+        let n = cmp::min(buf.len() as u64, self.len - self.pos) as usize;
+        try!(self.reader.seek(SeekFrom::Start(self.start + self.pos)));
+        let n = try!(self.reader.read(&mut buf[..n]));
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a, R:Read+Seek> Seek for SeekableEntry<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => self.len as i64 + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "seek to a position before the start of the entry"));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+// the local header of an entry with what extracting it takes: the crc32 and sizes, which
+// may come from a Zip64 extra field or the central directory instead, and where the data starts
+struct LocalEntry {
+    header: format::LocalFileHeader,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    data_pos: u64,
+}
+
+// where `inflate_entry` has the inflater write: passes on the first `len` bytes, taking the
+// crc32 of them and reporting progress, and drops the rest, which only an entry larger than
+// it claims has
+struct EntryWriter<'a, T:'a> {
+    writer: &'a mut T,
+    progress: &'a mut FnMut(u64, u64),
+    crc: u32,
+    written: u64,
+    len: u64,
+}
+
+impl<'a, T:Write> Write for EntryWriter<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = cmp::min(buf.len() as u64, self.len - self.written) as usize;
+        if n > 0 {
+            try!(self.writer.write_all(&buf[..n]));
+            self.crc = crc32::update(self.crc, &buf[..n]);
+            self.written += n as u64;
+            (self.progress)(self.written, self.len);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<R> ZipReader<R> {
+    fn cached_files(&self) -> &[FileInfo] {
+        match self.file_cache {
+            Some(ref files) => &files[..],
+            None => &[]
+        }
+    }
+
+    /// Returns the number of entries, as recorded in the end of central directory record,
+    /// or the Zip64 one when the archive has it.
+    pub fn entry_count(&self) -> usize {
+        match self.zip64_record {
+            Some(ref record) => record.total_entry_count as usize,
+            None => self.end_record.total_entry_count as usize
+        }
+    }
+
+    /// Returns the offset of the central directory from the start of the file, taken from
+    /// the Zip64 end record when the archive has it.
+    pub fn central_directory_offset(&self) -> u64 {
+        match self.zip64_record {
+            Some(ref record) => record.central_directory_offset,
+            None => self.end_record.central_directory_offset as u64
+        }
+    }
+
+    // as above, for the size of the central directory
+    fn central_directory_size(&self) -> u64 {
+        match self.zip64_record {
+            Some(ref record) => record.central_directory_size,
+            None => self.end_record.central_directory_size as u64
+        }
+    }
+}
+
 impl ZipReader<File> {
     pub fn open(path: &Path) -> Result<ZipReader<File>, ZipError> {
-        ZipReader::new(try_io!(File::open(path)))
+        ZipReader::new(try!(File::open(path)))
     }
 }
 
-impl<R:Reader+Seek> ZipReader<R> {
+impl ZipReader<Cursor<Vec<u8>>> {
+    /// Reads an archive that is already in memory.
+    pub fn from_bytes(data: Vec<u8>) -> Result<ZipReader<Cursor<Vec<u8>>>, ZipError> {
+        ZipReader::new(Cursor::new(data))
+    }
+}
+
+impl<R:Read+Seek> ZipReader<R> {
     pub fn new(reader: R) -> Result<ZipReader<R>, ZipError> {
-        // find the End of Central Directory record, looking backwards from the end of the file
+        ZipReader::with_options(reader, ZipReaderOptions::new())
+    }
+
+    pub fn with_options(reader: R, options: ZipReaderOptions) -> Result<ZipReader<R>, ZipError> {
         let mut r = reader;
-        try_io!(r.seek(0, SeekEnd));
-        let file_size = try_io!(r.tell());
-        let mut end_record_offset : Option<u64> = None;
-        for i in range_inclusive(4, file_size) {
+        let (e, size, start, zip64_record) = try!(ZipReader::find_end_record(&mut r));
+        Ok(ZipReader {reader: r, end_record: e, archive_size: size, archive_start: start,
+                      zip64_record: zip64_record, options: options, file_cache: None,
+                      data_offsets: HashMap::new(), header_offsets: HashMap::new()})
+    }
+
+    /// Salvages what it can from an archive without a usable central directory, such as an
+    /// interrupted download, by walking the local file headers from the start of the file.
+    /// Every entry whose data is complete is returned, up to the first one that isn't or
+    /// that can't be parsed. The entries can be extracted with `StreamingZipReader`.
+    ///
+    /// As with `StreamingZipReader`, entries with a data descriptor are only found if
+    /// it has a signature.
+    pub fn recover(mut reader: R) -> Result<Vec<FileInfo>, ZipError> {
+        let file_size = try!(reader.seek(SeekFrom::End(0)));
+        let mut files = Vec::new();
+        let mut offset = 0;
+        loop {
+            try!(reader.seek(SeekFrom::Start(offset)));
+            let header = match format::LocalFileHeader::read(&mut reader) {
+                Ok(header) => header,
+                Err(_) => break
+            };
+            let mut info = FileInfo::from_local_header(&header, offset);
+            let mut next = offset + header.total_size();
+            if header.has_data_descriptor() {
+                let dd = match read_until_data_descriptor(&mut BufReader::new(&mut reader),
+                                                          header.is_zip64(), &mut next) {
+                    Ok((_, dd)) => dd,
+                    Err(_) => break
+                };
+                info.crc32 = dd.crc32;
+                info.compressed_size = dd.compressed_size;
+                info.uncompressed_size = dd.uncompressed_size;
+            } else {
+                next += info.compressed_size;
+            }
+            if next > file_size {
+                break;
+            }
+            files.push(info);
+            offset = next;
+        }
+        if files.is_empty() {
+            return Err(ZipError::NotAZipFile);
+        }
+        Ok(files)
+    }
+
+    // returns the End of Central Directory record along with the size of the whole file, the
+    // start of the archive in it and the Zip64 end record, if a locator for it precedes the
+    // record; the directory offsets of both records are made absolute
+    fn find_end_record(r: &mut R) -> Result<(format::EndOfCentralDirectoryRecord, u64, u32,
+                                             Option<format::Zip64EndOfCentralDirectoryRecord>), ZipError> {
+        // find the End of Central Directory record, looking backwards from the end of the file
+        let file_size = try!(r.seek(SeekFrom::End(0)));
+        for i in 4..file_size + 1 {
             let offset = file_size - i;
-            try_io!(r.seek(offset as i64, SeekSet));
+            try!(r.seek(SeekFrom::Start(offset)));
 
-            let sig = try_io!(r.read_le_u32());
+            let sig = try!(r.read_le_u32());
+            if sig != format::EOCDR_SIGNATURE {
+                continue;
+            }
 
-            // TODO: check for false positives here
-            if sig == format::EOCDR_SIGNATURE {
-                end_record_offset = Some(offset);
-                break;
+            // the comment may contain the signature itself, so only accept a record whose
+            // comment ends exactly at the end of the file and whose directory precedes it
+            try!(r.seek(SeekFrom::Start(offset)));
+            let mut e = match format::EndOfCentralDirectoryRecord::read(r) {
+                Ok(e) => e,
+                Err(_) => continue
+            };
+            let directory_end = e.central_directory_offset as u64 + e.central_directory_size as u64;
+            // archives with a directory starting past 4 GB leave the offset to the Zip64 record
+            let zip64_offset = e.central_directory_offset == 0xffffffff;
+            if offset + 22 + e.comment.len() as u64 == file_size && (zip64_offset || directory_end <= offset) {
+                // only the last disk of a spanned set has an end record; reading it alone
+                // would follow offsets into the other disks
+                if e.disk_number != 0 || e.disk_number_with_start_of_central_directory != 0 ||
+                   e.entry_count_this_disk != e.total_entry_count {
+                    return Err(ZipError::UnsupportedMultiDisk);
+                }
+                let start = if zip64_offset { 0 } else { try!(ZipReader::find_archive_start(r, &e, offset)) };
+                e.central_directory_offset += start;
+                let zip64_locator = offset >= format::ZIP64_EOCDL_SIZE && {
+                    try!(r.seek(SeekFrom::Start(offset - format::ZIP64_EOCDL_SIZE)));
+                    try!(r.read_le_u32()) == format::ZIP64_EOCDL_SIGNATURE
+                };
+                let zip64_record = if zip64_locator {
+                    Some(try!(ZipReader::read_zip64_record(r, offset, start)))
+                } else {
+                    None
+                };
+                return Ok((e, file_size, start, zip64_record));
+            }
+        }
+        Err(ZipError::NotAZipFile)
+    }
+
+    // reads the Zip64 end record through the locator before the end record at `record_offset`
+    fn read_zip64_record(r: &mut R, record_offset: u64,
+                         start: u32) -> Result<format::Zip64EndOfCentralDirectoryRecord, ZipError> {
+        let locator_offset = record_offset - format::ZIP64_EOCDL_SIZE;
+        try!(r.seek(SeekFrom::Start(locator_offset)));
+        let locator = try!(format::Zip64EndOfCentralDirectoryLocator::read(r)
+                           .map_err(|e| at_offset(e, locator_offset)));
+        if locator.disk_number_with_zip64_end_record != 0 || locator.total_disk_count > 1 {
+            return Err(ZipError::UnsupportedMultiDisk);
+        }
+        // the locator's offset doesn't count anything prepended to the archive either
+        let zip64_offset = locator.zip64_end_record_offset + start as u64;
+        try!(r.seek(SeekFrom::Start(zip64_offset)));
+        let mut record = try!(format::Zip64EndOfCentralDirectoryRecord::read(r)
+                              .map_err(|e| at_offset(e, zip64_offset)));
+        if record.disk_number != 0 || record.disk_number_with_start_of_central_directory != 0 ||
+           record.entry_count_this_disk != record.total_entry_count {
+            return Err(ZipError::UnsupportedMultiDisk);
+        }
+        record.central_directory_offset += start as u64;
+        // every header takes at least 46 bytes, so a larger count can't be right and would
+        // have the entries preallocated for nothing
+        if record.central_directory_offset + record.central_directory_size > locator_offset ||
+           record.total_entry_count > record.central_directory_size / 46 {
+            return Err(ZipError::InvalidOffset(zip64_offset));
+        }
+        Ok(record)
+    }
+
+    // Offsets in an archive with something prepended, like the executable of a self-extractor,
+    // are usually still relative to the start of the archive. There the central directory isn't
+    // where the end record says, but right before the end record; the difference is the size
+    // of what was prepended.
+    fn find_archive_start(r: &mut R, e: &format::EndOfCentralDirectoryRecord,
+                          record_offset: u64) -> Result<u32, ZipError> {
+        let claimed = e.central_directory_offset as u64;
+        let actual = record_offset - e.central_directory_size as u64;
+        if actual == claimed || e.total_entry_count == 0 {
+            return Ok(0);
+        }
+        try!(r.seek(SeekFrom::Start(claimed)));
+        if try!(r.read_le_u32()) == format::CDH_SIGNATURE {
+            return Ok(0);
+        }
+        try!(r.seek(SeekFrom::Start(actual)));
+        if try!(r.read_le_u32()) == format::CDH_SIGNATURE {
+            return Ok((actual - claimed) as u32);
+        }
+        Ok(0)
+    }
+
+    /// Returns the size of anything prepended to the archive, like the executable of a
+    /// self-extracting archive, when the offsets in the archive don't account for it.
+    /// The offsets in `FileInfo` and the central directory headers handed out are
+    /// adjusted to be from the start of the file.
+    pub fn archive_start(&self) -> u64 {
+        self.archive_start as u64
+    }
+
+    /// Forgets the cached central directory and reads the end of central directory record
+    /// again, for when the underlying file has changed.
+    pub fn reload(&mut self) -> Result<(), ZipError> {
+        self.file_cache = None;
+        self.data_offsets.clear();
+        self.header_offsets.clear();
+        let (e, size, start, zip64_record) = try!(ZipReader::find_end_record(&mut self.reader));
+        self.end_record = e;
+        self.archive_size = size;
+        self.archive_start = start;
+        self.zip64_record = zip64_record;
+        Ok(())
+    }
+
+    /// Tells whether the archive uses Zip64, because a Zip64 end of central directory locator
+    /// was found or because fields of the end record hold the values that defer to the Zip64
+    /// records. The Zip64 end record a locator points to is read when the archive is opened,
+    /// and its entry count and directory offset and size take the place of the end record's.
+    pub fn is_zip64(&self) -> bool {
+        let e = &self.end_record;
+        self.zip64_record.is_some() ||
+            e.disk_number == 0xffff ||
+            e.disk_number_with_start_of_central_directory == 0xffff ||
+            e.entry_count_this_disk == 0xffff ||
+            e.total_entry_count == 0xffff ||
+            e.central_directory_size == 0xffffffff ||
+            e.central_directory_offset - self.archive_start == 0xffffffff
+    }
+
+    /// Returns the size of the whole archive, as found when it was opened.
+    pub fn archive_size(&self) -> u64 {
+        self.archive_size
+    }
+
+    /// Gives access to the underlying reader.
+    pub fn get_mut<'a>(&'a mut self) -> &'a mut R {
+        &mut self.reader
+    }
+
+    fn load_files(&mut self) -> Result<(), ZipError> {
+        if self.file_cache.is_none() {
+            let mut files = Vec::with_capacity(self.entry_count());
+            for info in self.files_raw() {
+                files.push(try!(info));
             }
+            self.file_cache = Some(files);
+        }
+        Ok(())
+    }
+
+    /// In strict mode, the crc32 and sizes of an entry's local file header are checked
+    /// against the central directory before extracting it, stored entries must have equal
+    /// compressed and uncompressed sizes, and partial reads ending short of the requested
+    /// length are errors. Off by default.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.options.strict = strict;
+    }
+
+    /// Refuses to extract entries that decompress to more than `limit` bytes, guarding against
+    /// decompression bombs. Entries declaring a larger size are rejected before being read.
+    /// Off by default; an entry decompressing past its own declared size is always an error.
+    pub fn set_max_decompressed_size(&mut self, limit: u64) {
+        self.options.max_decompressed_size = Some(limit);
+    }
 
+    /// Sets the password used to decrypt AES-encrypted entries.
+    pub fn set_password(&mut self, password: &[u8]) {
+        self.options.password = Some(password.to_vec());
+    }
+
+    /// Returns the end of central directory record the archive was opened with.
+    pub fn end_record(&self) -> &format::EndOfCentralDirectoryRecord {
+        &self.end_record
+    }
+
+    /// Reads the central directory headers as stored, with the fields `FileInfo` leaves out.
+    pub fn central_directory_headers(&mut self) -> Result<Vec<format::CentralDirectoryHeader>, ZipError> {
+        let offset = self.central_directory_offset();
+        try!(self.reader.seek(SeekFrom::Start(offset)));
+        let mut headers = Vec::with_capacity(self.entry_count());
+        for _ in 0..self.entry_count() {
+            let offset = try!(self.reader.seek(SeekFrom::Current(0)));
+            let mut h = try!(format::CentralDirectoryHeader::read(&mut self.reader)
+                             .map_err(|e| at_offset(e, offset)));
+            // the offset of a header deferring to the Zip64 extra field stays as it is
+            if h.relative_offset_of_local_header != 0xffffffff {
+                h.relative_offset_of_local_header += self.archive_start;
+            }
+            headers.push(h);
         }
+        Ok(headers)
+    }
 
-        match end_record_offset {
-            Some(offset) => {
-                try_io!(r.seek(offset as i64, SeekSet));
-                let e = try!(format::EndOfCentralDirectoryRecord::read(&mut r));
-                Ok(ZipReader {reader: r, end_record: e})
-            },
-            None => Err(ZipError::NotAZipFile)
+    /// Reads the central directory header of the entry `f` as stored, with the fields
+    /// `FileInfo` leaves out.
+    pub fn central_directory_header(&mut self, f: &FileInfo) -> Result<format::CentralDirectoryHeader, ZipError> {
+        if !self.header_offsets.contains_key(&f.local_file_header_offset) {
+            // walking the directory records where the headers are
+            for info in self.files_raw() {
+                try!(info);
+            }
+        }
+        let offset = match self.header_offsets.get(&f.local_file_header_offset) {
+            Some(&offset) => offset,
+            None => return Err(ZipError::FileNotFoundInArchive(f.name.clone()))
+        };
+        try!(self.reader.seek(SeekFrom::Start(offset)));
+        let mut h = try!(format::CentralDirectoryHeader::read(&mut self.reader)
+                         .map_err(|e| at_offset(e, offset)));
+        if h.relative_offset_of_local_header != 0xffffffff {
+            h.relative_offset_of_local_header += self.archive_start;
+        }
+        Ok(h)
+    }
+
+    /// Reads the central directory exactly as stored, e.g. to compute a digest over it.
+    pub fn central_directory_bytes(&mut self) -> Result<Vec<u8>, ZipError> {
+        let offset = self.central_directory_offset();
+        try!(self.reader.seek(SeekFrom::Start(offset)));
+        let size = self.central_directory_size();
+        Ok(try!(self.reader.read_vec(size as usize)))
+    }
+
+    /// Returns the digital signature record following the central directory headers, if any.
+    /// The signature isn't checked; the bytes it covers are left to the caller.
+    pub fn digital_signature(&mut self) -> Result<Option<format::CentralDirectoryDigitalSignature>, ZipError> {
+        try!(self.central_directory_headers());
+        let offset = try!(self.reader.seek(SeekFrom::Current(0)));
+        match format::CentralDirectoryDigitalSignature::read(&mut self.reader) {
+            Ok(signature) => Ok(Some(signature)),
+            Err(ZipError::InvalidSignature(..)) => Ok(None),
+            Err(e) => Err(at_offset(e, offset))
         }
     }
 
+    /// Returns the sum of the uncompressed sizes of all entries.
+    pub fn total_uncompressed_size(&mut self) -> Result<u64, ZipError> {
+        let mut total = 0u64;
+        for info in self.files_raw() {
+            total += try!(info).uncompressed_size;
+        }
+        Ok(total)
+    }
+
     pub fn files_raw<'a>(&'a mut self) -> RawFiles<'a, R> {
-        let cdr_offset = self.end_record.central_directory_offset;
+        let cdr_offset = self.central_directory_offset();
         RawFiles {
             zip_reader: self,
             current_entry: 0,
-            current_offset: cdr_offset as u64
+            current_offset: cdr_offset
         }
     }
 
+    /// Iterates over the entries, yielding an error (and then stopping) when a central
+    /// directory header can't be read. This is the recommended way to list an archive.
+    pub fn try_files<'a>(&'a mut self) -> RawFiles<'a, R> {
+        self.files_raw()
+    }
+
+    /// Iterates over the entries. The central directory is parsed once and cached,
+    /// later calls (and `info` or `by_index`) don't read it again until `reload`.
+    ///
+    /// This panics when a central directory header can't be read, as happens with a
+    /// corrupt archive; use `try_files` to handle such archives gracefully.
     pub fn files<'a>(&'a mut self) -> Files<'a, R> {
-        Files { base: self.files_raw() }
+        self.load_files().unwrap();
+        Files { zip_reader: self, index: 0 }
     }
 
+    /// As `files`, but only the entries that are files. Besides the entries `FileInfo::is_dir`
+    /// takes for directories, empty entries whose name ends with a backslash are left out
+    /// too, as some Windows archivers store directories that way.
+    pub fn file_entries<'a>(&'a mut self) -> FileEntries<'a, R> {
+        FileEntries { base: self.files() }
+    }
+
+    /// Iterates over the entry names, ending at the first central directory header that
+    /// can't be read, so a partly corrupt archive lists the names before it. Use
+    /// `try_file_names` to tell such an archive from a complete one.
     pub fn file_names<'a>(&'a mut self) -> FileNames<'a, R> {
-        FileNames { base: self.files_raw() }
+        FileNames { base: self.try_files() }
+    }
+
+    /// Iterates over the entry names like `try_files` does, so that the names before a
+    /// broken central directory header can still be listed instead of `file_names` panicking.
+    pub fn try_file_names<'a>(&'a mut self) -> TryFileNames<'a, R> {
+        TryFileNames { base: self.try_files() }
+    }
+
+    /// Iterates over the raw bytes of the entry names, borrowed from the cached central
+    /// directory rather than cloned like `file_names` does, which matters for listing
+    /// very large archives.
+    pub fn file_names_bytes<'a>(&'a mut self) -> Result<FileNameBytes<'a>, ZipError> {
+        try!(self.load_files());
+        Ok(FileNameBytes { files: self.cached_files().iter() })
+    }
+
+    /// Iterates over every entry along with its decompressed contents, which is handy for
+    /// small archives. Directory entries come with an empty `Vec`.
+    pub fn entries<'a>(&'a mut self) -> Entries<'a, R> {
+        let error = self.load_files().err();
+        Entries { zip_reader: self, index: 0, error: error }
+    }
+
+    /// Hashes the name, crc32 and uncompressed size of every entry, as found in the central
+    /// directory, into a value that identifies the contents of the archive without
+    /// decompressing it.
+    /// Archives holding the same files in any order get the same fingerprint, whatever
+    /// their compression. The hash (64-bit FNV-1a) is stable across versions, so it can be
+    /// stored.
+    pub fn content_fingerprint(&mut self) -> Result<u64, ZipError> {
+        let mut infos: Vec<FileInfo> = try!(self.try_files().collect());
+        infos.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+        let mut hash = FNV_OFFSET_BASIS;
+        for info in infos.iter() {
+            // the length keeps a name from running into the fields that follow it
+            hash = fnv1a(hash, &(info.name.len() as u32).to_le_bytes());
+            hash = fnv1a(hash, info.name.as_bytes());
+            hash = fnv1a(hash, &info.crc32.to_le_bytes());
+            hash = fnv1a(hash, &info.uncompressed_size.to_le_bytes());
+        }
+        Ok(hash)
+    }
+
+    /// Formats the central directory as a table in the style of `unzip -l`, with the sizes,
+    /// compression ratio and modification time of each entry and a line of totals.
+    /// Names that aren't valid UTF-8 are shown with replacement characters.
+    pub fn format_listing(&mut self) -> Result<String, ZipError> {
+        try!(self.load_files());
+        let mut out = String::new();
+        out.push_str("    Length  Compressed  Ratio  Date       Time      Name\n");
+        out.push_str("----------  ----------  -----  ---------- --------  ----\n");
+        let (mut total, mut total_compressed) = (0u64, 0u64);
+        for f in self.cached_files().iter() {
+            let (year, month, day, hour, minute, second) = f.last_modified_datetime;
+            out.push_str(&format!("{:>10}  {:>10}  {:>4.0}%  {:04}-{:02}-{:02} {:02}:{:02}:{:02}  {}\n",
+                                  f.uncompressed_size, f.compressed_size, f.compression_ratio() * 100.0,
+                                  year, month, day, hour, minute, second, f.name.as_cow_lossy()));
+            total += f.uncompressed_size;
+            total_compressed += f.compressed_size;
+        }
+        let ratio = if total == 0 { 1.0 } else { total_compressed as f64 / total as f64 };
+        out.push_str("----------  ----------  -----                       ----\n");
+        out.push_str(&format!("{:>10}  {:>10}  {:>4.0}%                       {} file(s)\n",
+                              total, total_compressed, ratio * 100.0, self.cached_files().len()));
+        Ok(out)
+    }
+
+    /// Returns the entries under the given directory, taking `\` and `/` as the same separator.
+    /// The prefix is a whole directory name, so `foo` doesn't match `foobar/`;
+    /// an empty prefix returns every entry.
+    pub fn files_with_prefix(&mut self, prefix: &str) -> Result<Vec<FileInfo>, ZipError> {
+        let mut dir: Vec<u8> = prefix.bytes().map(normalize_separator).collect();
+        if !dir.is_empty() && dir.last() != Some(&b'/') {
+            dir.push(b'/');
+        }
+        try!(self.load_files());
+        Ok(self.cached_files().iter().filter(|f| {
+            let name = f.name.as_bytes();
+            name.len() >= dir.len() &&
+                name.iter().zip(dir.iter()).all(|(&a, &b)| normalize_separator(a) == b)
+        }).cloned().collect())
+    }
+
+    /// Returns the entries in the order they are stored in the file rather than the order of
+    /// the central directory, so that extracting them one after the other reads the file
+    /// from front to back instead of seeking around.
+    pub fn files_by_offset(&mut self) -> Result<Vec<FileInfo>, ZipError> {
+        try!(self.load_files());
+        let mut files = self.cached_files().to_vec();
+        files.sort_by(|a, b| a.local_file_header_offset.cmp(&b.local_file_header_offset));
+        Ok(files)
+    }
+
+    /// Returns the entry at the given position in the central directory.
+    pub fn by_index(&mut self, index: usize) -> Result<FileInfo, ZipError> {
+        try!(self.load_files());
+        match self.cached_files().get(index) {
+            Some(info) => Ok(info.clone()),
+            None => Err(ZipError::FileNotFoundInArchive(MaybeUtf8Buf::new()))
+        }
+    }
+
+    /// Looks up an entry by its exact name, given as a `&str`, `String`, `&[u8]` or `Vec<u8>`.
+    pub fn info<T>(&mut self, name: T) -> Result<FileInfo, ZipError>
+            where T: AsRef<[u8]> {
+        self.find_with(name, |a, b| a == b)
+    }
+
+    /// Tells whether an entry with this name exists, without cloning its `FileInfo`. `\` and
+    /// `/` are taken as the same separator, on both sides; case has to match. An archive whose
+    /// central directory can't be read contains nothing.
+    pub fn contains<T>(&mut self, name: T) -> bool
+            where T: AsRef<[u8]> {
+        if self.load_files().is_err() {
+            return false;
+        }
+        self.cached_files().iter().any(|i| separator_eq(i.name.as_bytes(), name.as_ref()))
     }
 
-    pub fn info<'a, T>(&mut self, name: T) -> Result<FileInfo, ZipError>
-            where T: IntoMaybeUtf8<MaybeUtf8Slice<'a>> {
-        let name = name.into_maybe_utf8();
-        for i in self.files() {
-            if i.name == name.as_bytes() {
-                return Ok(i);
+    /// Looks up an entry by name, ignoring ASCII case.
+    pub fn info_ignore_case<T>(&mut self, name: T) -> Result<FileInfo, ZipError>
+            where T: AsRef<[u8]> {
+        self.find_with(name, |a, b| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Looks up an entry by name, ignoring ASCII case and taking `\` and `/` as the same
+    /// separator, since archives created on Windows frequently use backslashes.
+    pub fn find_normalized<T>(&mut self, name: T) -> Result<FileInfo, ZipError>
+            where T: AsRef<[u8]> {
+        self.find_with(name, normalized_eq)
+    }
+
+    fn find_with<T, F>(&mut self, name: T, matches: F) -> Result<FileInfo, ZipError>
+            where T: AsRef<[u8]>, F: Fn(&[u8], &[u8]) -> bool {
+        let name = name.as_ref();
+        try!(self.load_files());
+        for i in self.cached_files().iter() {
+            if matches(i.name.as_bytes(), name) {
+                return Ok(i.clone());
             }
         }
-        Err(ZipError::FileNotFoundInArchive)
+        // keep names that were given as strings printable in the error
+        let name = match str::from_utf8(name) {
+            Ok(s) => MaybeUtf8Buf::from_str(s.to_string()),
+            Err(_) => MaybeUtf8Buf::from_bytes(name.to_vec())
+        };
+        Err(ZipError::FileNotFoundInArchive(name))
     }
     
-    pub fn extract_file<T:Writer>(&mut self, f: &FileInfo, writer: &mut T) -> Result<(), ZipError> {
-        match self.read(f, -1 as usize) {
-            Ok(bytes) => { try_io!(writer.write_all(&bytes[..])); Ok(()) },
-            Err(x) => Err(x)
+    /// Looks up the entry with the given name and extracts it into `writer`,
+    /// verifying its CRC. Returns the number of bytes written.
+    pub fn unzip_one<T, W>(&mut self, name: T, writer: &mut W) -> Result<u64, ZipError>
+            where T: AsRef<[u8]>, W: Write {
+        let info = try!(self.info(name));
+        let mut written = 0;
+        try!(self.extract_file_with_progress(&info, writer, &mut |done, _| written = done));
+        Ok(written)
+    }
+
+    /// As `unzip_one`, without the count: extracts the entry with the given name into `writer`
+    /// like `extract_file`, failing with `FileNotFoundInArchive` if there is none.
+    pub fn extract_by_name<T, W>(&mut self, name: T, writer: &mut W) -> Result<(), ZipError>
+            where T: AsRef<[u8]>, W: Write {
+        self.unzip_one(name, writer).map(|_| ())
+    }
+
+    /// Returns a reader over the decompressed contents of the given entry.
+    pub fn open_entry(&mut self, f: &FileInfo) -> Result<EntryReader, ZipError> {
+        let bytes = try!(self.read(f, usize::MAX));
+        Ok(EntryReader { inner: Cursor::new(bytes) })
+    }
+
+    /// Returns a reader over the contents of a stored entry that can seek, reading from the
+    /// archive as needed instead of extracting the entry first. Compressed entries can't be
+    /// read at arbitrary positions and fail with `UnsupportedCompressionMethod`.
+    pub fn open_entry_seekable<'a>(&'a mut self, f: &FileInfo) -> Result<SeekableEntry<'a, R>, ZipError> {
+        let entry = try!(self.entry_header(f));
+        let method = entry.header.compression_method;
+        if CompressionMethod::from_u16(method) != CompressionMethod::Store {
+            return Err(ZipError::UnsupportedCompressionMethod(method));
+        }
+        let len = cmp::min(entry.compressed_size, entry.uncompressed_size);
+        Ok(SeekableEntry { reader: &mut self.reader, start: entry.data_pos, len: len, pos: 0 })
+    }
+
+    /// Returns the top-level directory shared by every entry, if there is one.
+    /// Archives made from a single folder (GitHub tarballs, for example) look like this.
+    pub fn common_prefix(&mut self) -> Result<Option<MaybeUtf8Buf>, ZipError> {
+        let mut prefix: Option<MaybeUtf8Buf> = None;
+        for info in self.files_raw() {
+            let info = try!(info);
+            let name = info.name.as_bytes();
+            let component = match name.iter().position(|&b| b == b'/') {
+                Some(pos) => &name[..pos],
+                None => return Ok(None) // a file at the top level
+            };
+            let same = match prefix {
+                Some(ref p) => p.as_bytes() == component,
+                None => true
+            };
+            if !same {
+                return Ok(None);
+            }
+            if prefix.is_none() {
+                prefix = Some(match info.name.as_str() {
+                    Some(s) => MaybeUtf8Buf::from_str(s[..component.len()].to_string()),
+                    None => MaybeUtf8Buf::from_bytes(component.to_vec())
+                });
+            }
+        }
+        Ok(prefix)
+    }
+
+    /// Extracts every file entry into memory, keyed by its sanitized relative path.
+    /// Directory entries are skipped.
+    pub fn into_path_map(mut self) -> Result<HashMap<PathBuf, Vec<u8>>, ZipError> {
+        let mut infos = Vec::new();
+        for info in self.files_raw() {
+            infos.push(try!(info));
+        }
+        let mut map = HashMap::new();
+        for info in infos.iter() {
+            if info.is_dir() {
+                continue;
+            }
+            if let Some(path) = info.sanitized_path() {
+                let bytes = try!(self.read(info, usize::MAX));
+                map.insert(path, bytes);
+            }
+        }
+        Ok(map)
+    }
+
+    /// Extracts every file entry directly into `dest`, ignoring the directory structure.
+    /// When several entries share a base name, the later ones get a counter appended
+    /// (`a.txt`, `a_1.txt`, `a_2.txt`...). Directory entries are skipped.
+    pub fn extract_flattened(&mut self, dest: &Path) -> Result<(), ZipError> {
+        let infos: Vec<FileInfo> = try!(self.try_files().collect());
+        let mut used = HashSet::new();
+        for info in infos.iter() {
+            if info.is_dir() {
+                continue;
+            }
+            let full_name = info.name.as_cow_lossy();
+            let base = full_name.split(|c: char| c == '/' || c == '\\').last().unwrap_or("");
+            if base == "" || base == "." || base == ".." {
+                continue;
+            }
+            let mut name = base.to_string();
+            let mut n = 0;
+            while used.contains(&name) {
+                n += 1;
+                name = numbered_name(base, n);
+            }
+            let bytes = try!(self.read(info, usize::MAX));
+            let mut file = try!(File::create(dest.join(&name)));
+            try!(file.write_all(&bytes[..]));
+            used.insert(name);
+        }
+        Ok(())
+    }
+
+    /// Extracts every entry under `dest`, keeping the directory structure. Entry names are
+    /// sanitized first (see `FileInfo::sanitized_path`) so nothing is written outside `dest`.
+    /// `should_cancel` is checked before each entry and every 64 KB or so within it; once it
+    /// returns `true` extraction stops with `ZipError::Cancelled`, leaving the entries
+    /// extracted so far in place. The file of an entry that fails, or whose extraction is
+    /// cancelled, is removed.
+    pub fn extract_to<F>(&mut self, dest: &Path, mut should_cancel: F) -> Result<(), ZipError>
+            where F: FnMut() -> bool {
+        let infos: Vec<FileInfo> = try!(self.try_files().collect());
+        for info in infos.iter() {
+            if should_cancel() {
+                return Err(ZipError::Cancelled);
+            }
+            let path = match info.sanitized_path() {
+                Some(path) => path,
+                None => continue
+            };
+            let target = dest.join(&path);
+            if info.is_dir() {
+                try!(fs::create_dir_all(&target));
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                try!(fs::create_dir_all(parent));
+            }
+            let mut file = try!(File::create(&target));
+            let r = self.extract_unnamed(info, &mut file, &mut |_, _| {}, &mut should_cancel);
+            if let Err(e) = r {
+                drop(file);
+                let _ = fs::remove_file(&target);
+                return Err(e.with_name(&info.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Decompresses every entry and checks its crc32 without writing anything out,
+    /// returning the first error found. `should_cancel` is checked before and within each
+    /// entry as in `extract_to`.
+    pub fn test_archive<F>(&mut self, mut should_cancel: F) -> Result<(), ZipError>
+            where F: FnMut() -> bool {
+        let infos: Vec<FileInfo> = try!(self.try_files().collect());
+        for info in infos.iter() {
+            if should_cancel() {
+                return Err(ZipError::Cancelled);
+            }
+            try!(self.extract_unnamed(info, &mut io::sink(), &mut |_, _| {}, &mut should_cancel)
+                 .map_err(|e| e.with_name(&info.name)));
+        }
+        Ok(())
+    }
+
+    /// Decompresses the entry and checks its crc32, discarding the contents. The crc32 is
+    /// checked even when `ZipReaderOptions::verify_crc` is off.
+    pub fn verify(&mut self, f: &FileInfo) -> Result<(), ZipError> {
+        let entry = try!(self.entry_header(f).map_err(|e| e.with_name(&f.name)));
+        self.extract_entry(&entry, &mut io::sink(), &mut |_, _| {}, &mut || false).map_err(|e| e.with_name(&f.name))
+    }
+
+    /// Verifies every entry like `unzip -t`, returning the name of the first one that fails
+    /// along with the reason, or `None` if they are all intact. Errors reading the central
+    /// directory itself are returned as they are.
+    pub fn verify_all(&mut self) -> Result<Option<(MaybeUtf8Buf, ZipError)>, ZipError> {
+        let infos: Vec<FileInfo> = try!(self.try_files().collect());
+        for info in infos.iter() {
+            if let Err(e) = self.verify(info) {
+                return Ok(Some((info.name.clone(), e)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Extracts the entry into `writer`, verifying its crc32. Stored entries are copied over
+    /// a chunk at a time rather than read into memory first, and Deflate and Deflate64 ones
+    /// are written as they are inflated, holding no more than the compressed data and the
+    /// last 64 KB of output in memory; either way the crc32 of large entries is only checked
+    /// once all of the data has been written. Entries of other methods are decompressed whole.
+    pub fn extract_file<T:Write>(&mut self, f: &FileInfo, writer: &mut T) -> Result<(), ZipError> {
+        self.extract_unnamed(f, writer, &mut |_, _| {}, &mut || false).map_err(|e| e.with_name(&f.name))
+    }
+
+    /// As `extract_file`, calling `progress` with the number of bytes written so far and the
+    /// uncompressed size of the entry. Stored entries report after every chunk and Deflate and
+    /// Deflate64 ones every 64 KB or so of output; the others, which are decompressed whole,
+    /// only once they are done.
+    pub fn extract_file_with_progress<T:Write>(&mut self, f: &FileInfo, writer: &mut T,
+                                               progress: &mut FnMut(u64, u64)) -> Result<(), ZipError> {
+        self.extract_unnamed(f, writer, progress, &mut || false).map_err(|e| e.with_name(&f.name))
+    }
+
+    // `cancel` is checked as the entry is extracted, see `copy_stored` and `inflate::inflate`
+    fn extract_unnamed<T:Write>(&mut self, f: &FileInfo, writer: &mut T, progress: &mut FnMut(u64, u64),
+                                cancel: &mut FnMut() -> bool) -> Result<(), ZipError> {
+        let entry = try!(self.extraction_header(f));
+        self.extract_entry(&entry, writer, progress, cancel)
+    }
+
+    fn extract_entry<T:Write>(&mut self, entry: &LocalEntry, writer: &mut T, progress: &mut FnMut(u64, u64),
+                              cancel: &mut FnMut() -> bool) -> Result<(), ZipError> {
+        if CompressionMethod::from_u16(entry.header.compression_method) == CompressionMethod::Store {
+            return self.copy_stored(entry, writer, progress, cancel);
+        }
+        match CompressionMethod::from_u16(self.effective_method(&entry.header)) {
+            CompressionMethod::Deflate => return self.inflate_entry(entry, false, writer, progress, cancel),
+            CompressionMethod::Deflate64 if cfg!(feature = "deflate64") =>
+                return self.inflate_entry(entry, true, writer, progress, cancel),
+            _ => {}
         }
+        let bytes = try!(self.read_data(entry, usize::MAX, cancel));
+        try!(writer.write_all(&bytes[..]));
+        progress(bytes.len() as u64, entry.uncompressed_size);
+        Ok(())
+    }
+
+    /// As `extract_file`, but into `buf`, which is cleared first. Reusing one buffer saves
+    /// allocating a new `Vec` for every entry when extracting many small ones.
+    pub fn read_into(&mut self, f: &FileInfo, buf: &mut Vec<u8>) -> Result<(), ZipError> {
+        buf.clear();
+        let entry = try!(self.extraction_header(f).map_err(|e| e.with_name(&f.name)));
+        // the size has passed the checks by now, but is still only what the headers claim
+        buf.reserve(cmp::min(entry.uncompressed_size, MAX_RESERVED_SIZE) as usize);
+        self.extract_entry(&entry, buf, &mut |_, _| {}, &mut || false).map_err(|e| e.with_name(&f.name))
     }
 
-    pub fn extract_first<T:Writer>(&mut self, f: &FileInfo, len: usize, writer: &mut T) -> Result<(), ZipError> {
+    // the streaming counterpart of `decode` for stored data; `cancel` is checked before
+    // every chunk but the first
+    fn copy_stored<T:Write>(&mut self, entry: &LocalEntry, writer: &mut T, progress: &mut FnMut(u64, u64),
+                            cancel: &mut FnMut() -> bool) -> Result<(), ZipError> {
+        try!(self.reader.seek(SeekFrom::Start(entry.data_pos)));
+        let mut remaining = entry.compressed_size;
+        let mut buf = vec![0u8; cmp::min(remaining, COPY_BUFFER_SIZE as u64) as usize];
+        let mut to_write = entry.uncompressed_size;
+        let mut crc = 0;
+        while remaining > 0 {
+            if remaining < entry.compressed_size && cancel() {
+                return Err(ZipError::Cancelled);
+            }
+            let n = cmp::min(remaining, buf.len() as u64) as usize;
+            try!(self.reader.read_exact(&mut buf[..n]));
+            if entry.crc32 != 0 {
+                crc = crc32::update(crc, &buf[..n]);
+            }
+            let w = cmp::min(to_write, n as u64) as usize;
+            try!(writer.write_all(&buf[..w]));
+            to_write -= w as u64;
+            remaining -= n as u64;
+            progress(entry.uncompressed_size - to_write, entry.uncompressed_size);
+        }
+        if entry.compressed_size == 0 {
+            progress(0, entry.uncompressed_size);
+        }
+        if entry.crc32 != 0 && entry.crc32 != crc {
+            return Err(ZipError::CrcError(MaybeUtf8Buf::new()));
+        }
+        Ok(())
+    }
+
+    // the streaming counterpart of `decompress`: the compressed data is read whole, but the
+    // inflater writes its output on as it goes. What was inflated before a broken stream has
+    // been written by the time the error comes back, so `PartiallyDecompressed` carries none
+    fn inflate_entry<T:Write>(&mut self, entry: &LocalEntry, deflate64: bool, writer: &mut T,
+                              progress: &mut FnMut(u64, u64), cancel: &mut FnMut() -> bool) -> Result<(), ZipError> {
+        try!(self.reader.seek(SeekFrom::Start(entry.data_pos)));
+        let data = try!(self.reader.read_vec(entry.compressed_size as usize));
+        let size = entry.uncompressed_size;
+        let mut out = EntryWriter { writer: writer, progress: progress, crc: 0, written: 0, len: size };
+        let inflated = match inflate::inflate(&data[..], deflate64, size, &mut out, cancel) {
+            Ok(inflated) => inflated,
+            Err(e) => return Err(inflate_failure(e, Vec::new(), 0, self.options.keep_partial_output))
+        };
+        if inflated > size {
+            return Err(ZipError::DecompressedSizeLimitExceeded);
+        }
+        if inflated != size {
+            return Err(ZipError::DecompressionFailure(MaybeUtf8Buf::new()));
+        }
+        if size == 0 {
+            (out.progress)(0, 0);
+        }
+        if entry.crc32 != 0 && entry.crc32 != out.crc {
+            return Err(ZipError::CrcError(MaybeUtf8Buf::new()));
+        }
+        Ok(())
+    }
+
+    /// As `extract_file`, but for entries flagged as text (see `FileInfo::is_text`), or any
+    /// entry when `force` is set, CRLF and lone CR line endings are converted to the platform's,
+    /// like `unzip -a` does. Other entries are extracted unchanged.
+    pub fn extract_file_text<T:Write>(&mut self, f: &FileInfo, writer: &mut T,
+                                      force: bool) -> Result<(), ZipError> {
+        let bytes = try!(self.read(f, usize::MAX));
+        if f.is_text() || force {
+            try!(writer.write_all(&convert_line_endings(&bytes, NEWLINE)[..]));
+        } else {
+            try!(writer.write_all(&bytes[..]));
+        }
+        Ok(())
+    }
+
+    /// Extracts the first `len` bytes of the entry into `writer`. Only that much of a stored
+    /// entry is read, and a Deflate or Deflate64 stream is inflated no further than `len`
+    /// bytes, though its compressed data is still read whole. Other methods are decompressed
+    /// whole before being cut short.
+    pub fn extract_first<T:Write>(&mut self, f: &FileInfo, len: usize, writer: &mut T) -> Result<(), ZipError> {
         match self.read(f, len) {
-            Ok(bytes) => { try_io!(writer.write_all(&bytes[..])); Ok(()) },
+            Ok(bytes) => { try!(writer.write_all(&bytes[..])); Ok(()) },
             Err(x) => Err(x)
         }
     }
     
+    /// Returns the offset in the file where the (compressed) data of the entry starts, for
+    /// reading it by other means such as a memory map. The local header is read only the
+    /// first time.
+    pub fn data_offset(&mut self, f: &FileInfo) -> Result<u64, ZipError> {
+        if let Some(&offset) = self.data_offsets.get(&f.local_file_header_offset) {
+            return Ok(offset);
+        }
+        let offset = f.local_file_header_offset;
+        try!(self.reader.seek(SeekFrom::Start(offset)));
+        let header = try!(format::LocalFileHeader::read(&mut self.reader)
+                          .map_err(|e| at_offset(e, offset)));
+        let data_offset = offset + header.total_size();
+        self.data_offsets.insert(f.local_file_header_offset, data_offset);
+        Ok(data_offset)
+    }
+
+    /// Returns the data of an entry exactly as stored, still compressed (and encrypted, if it is).
+    /// Useful for copying an entry to another archive without decompressing it.
+    pub fn read_raw(&mut self, f: &FileInfo) -> Result<Vec<u8>, ZipError> {
+        let entry = try!(self.locate_entry(f));
+        try!(self.reader.seek(SeekFrom::Start(entry.data_pos)));
+        Ok(try!(self.reader.read_vec(entry.compressed_size as usize)))
+    }
+
     fn read(&mut self, f: &FileInfo, wish_len: usize) -> Result<Vec<u8>, ZipError> {
-        try_io!(self.reader.seek(f.local_file_header_offset as i64, SeekSet));
-        let header = try!(format::LocalFileHeader::read(&mut self.reader));
-        let file_pos = f.local_file_header_offset as i64 + header.total_size() as i64;
-        let file_len = header.compressed_size as usize;
-        if wish_len > file_len {
-            self.extract_block(file_pos, file_len, header.compression_method, header.crc32)
+        self.read_unnamed(f, wish_len).map_err(|e| e.with_name(&f.name))
+    }
+
+    fn read_unnamed(&mut self, f: &FileInfo, wish_len: usize) -> Result<Vec<u8>, ZipError> {
+        let entry = try!(self.extraction_header(f));
+        self.read_data(&entry, wish_len, &mut || false)
+    }
+
+    // as `entry_header`, with the crc32 zeroed when it shouldn't be verified, which every
+    // decoder takes as "unknown"
+    fn extraction_header(&mut self, f: &FileInfo) -> Result<LocalEntry, ZipError> {
+        let mut entry = try!(self.entry_header(f));
+        if !self.options.verify_crc {
+            entry.crc32 = 0;
+        }
+        Ok(entry)
+    }
+
+    // reads and checks the local header of an entry, for extracting it
+    fn entry_header(&mut self, f: &FileInfo) -> Result<LocalEntry, ZipError> {
+        if f.requires_version() > SUPPORTED_VERSION {
+            return Err(ZipError::UnsupportedVersion(f.requires_version()));
+        }
+        if f.is_encrypted && self.options.password.is_none() {
+            return Err(ZipError::Encrypted);
+        }
+        let entry = try!(self.locate_entry(f));
+        try!(entry.header.check_supported());
+        match self.options.max_decompressed_size {
+            Some(limit) if entry.uncompressed_size > limit =>
+                return Err(ZipError::DecompressedSizeLimitExceeded),
+            _ => {}
+        }
+        Ok(entry)
+    }
+
+    // reads the local header of an entry and works out where its data is, checking that it
+    // lies within the archive; whether the data can be extracted is left to `entry_header`
+    fn locate_entry(&mut self, f: &FileInfo) -> Result<LocalEntry, ZipError> {
+        if f.disk_number_start != 0 {
+            return Err(ZipError::UnsupportedMultiDisk);
+        }
+        // entries have to lie before the central directory; a crafted archive could
+        // otherwise point into the directory itself or past the end of the file
+        let directory_offset = self.central_directory_offset();
+        let offset = f.local_file_header_offset;
+        if offset >= directory_offset {
+            return Err(ZipError::InvalidOffset(offset));
+        }
+        try!(self.reader.seek(SeekFrom::Start(offset)));
+        let header = try!(format::LocalFileHeader::read(&mut self.reader)
+                          .map_err(|e| at_offset(e, offset)));
+        let (mut crc32, mut compressed_size, mut uncompressed_size) =
+            (header.crc32, header.compressed_size as u64, header.uncompressed_size as u64);
+        if !header.has_data_descriptor() &&
+           (header.compressed_size == 0xffffffff || header.uncompressed_size == 0xffffffff) {
+            // Zip64 entries have their real sizes in the extra field
+            if let Some((uncompressed, compressed)) = header.zip64_sizes() {
+                uncompressed_size = uncompressed;
+                compressed_size = compressed;
+            }
+        }
+        if self.options.strict && !header.has_data_descriptor() &&
+           (crc32 != f.crc32 ||
+            compressed_size != f.compressed_size ||
+            uncompressed_size != f.uncompressed_size) {
+            return Err(ZipError::HeaderMismatch);
+        }
+        if header.has_data_descriptor() {
+            // the local fields are zero, the real values follow the data and are in the central directory
+            crc32 = f.crc32;
+            compressed_size = f.compressed_size;
+            uncompressed_size = f.uncompressed_size;
+        }
+        // stored data is its own uncompressed contents, so both sizes have to agree
+        if self.options.strict && compressed_size != uncompressed_size &&
+           CompressionMethod::from_u16(header.compression_method) == CompressionMethod::Store {
+            return Err(ZipError::HeaderMismatch);
+        }
+        let data_pos = offset + header.total_size();
+        if data_pos + compressed_size > directory_offset {
+            return Err(ZipError::InvalidOffset(offset));
+        }
+        Ok(LocalEntry { header: header, crc32: crc32, compressed_size: compressed_size,
+                        uncompressed_size: uncompressed_size, data_pos: data_pos })
+    }
+
+    fn read_data(&mut self, entry: &LocalEntry, wish_len: usize,
+                 cancel: &mut FnMut() -> bool) -> Result<Vec<u8>, ZipError> {
+        let file_len = entry.compressed_size as usize;
+        let method = self.effective_method(&entry.header);
+        if method == format::AES_COMPRESSION_METHOD {
+            return self.read_aes(entry, wish_len, cancel);
+        }
+        let file_pos = entry.data_pos;
+        let data_len = entry.uncompressed_size as usize;
+        let limit = entry.uncompressed_size;
+        if wish_len >= data_len {
+            self.extract_block(file_pos, file_len, data_len, method, entry.crc32, limit, cancel)
         } else {
-            self.extract_block(file_pos, wish_len, header.compression_method, 0)
+            // only stored data can be cut short; a Deflate stream is read whole but the
+            // inflater stops once it has `wish_len` bytes
+            let read_len = match CompressionMethod::from_u16(method) {
+                CompressionMethod::Store => wish_len,
+                _ => file_len
+            };
+            let bytes = try!(self.extract_block(file_pos, read_len, wish_len, method, 0, limit, cancel));
+            // the stream may end before `wish_len` if the declared size is wrong
+            if self.options.strict && bytes.len() < wish_len {
+                return Err(ZipError::DecompressionFailure(MaybeUtf8Buf::new()));
+            }
+            Ok(bytes)
         }
     }    
 
-    fn extract_block(&mut self, pos: i64, len: usize, method: u16, crc32: u32) -> Result<Vec<u8>, ZipError> {
-        try_io!(self.reader.seek(pos, SeekSet));
-        let compressed = try_io!(self.reader.read_exact(len));
+    // the compression method to decode the entry with, which is Store for unknown methods
+    // when the option to treat them so is set
+    fn effective_method(&self, header: &format::LocalFileHeader) -> u16 {
+        let method = header.compression_method;
+        if !self.options.treat_unknown_as_store {
+            return method;
+        }
+        if method == format::AES_COMPRESSION_METHOD &&
+           format::AesExtraField::from_extra_field(&header.extra_field).is_some() {
+            return method;
+        }
         match CompressionMethod::from_u16(method) {
-                CompressionMethod::Store   => Ok(compressed),
-                CompressionMethod::Deflate => self.decompress(compressed, len, crc32),
-                _ => panic!("Usupported CompressionMethod")
+            CompressionMethod::Unknown(_) => CompressionMethod::Store.to_u16(),
+            _ => method
         }
     }
 
-    fn decompress(&mut self, data: Vec<u8>, len:usize, crc32: u32) -> Result<Vec<u8>, ZipError> 
-    {        
-        let bytes = match flate::inflate_bytes(&data[..])
-        {
-            Ok(decompressed) => decompressed,
-            Err(what) => return Err(ZipError::DecompressionFailure)
+    fn read_aes(&mut self, entry: &LocalEntry, wish_len: usize,
+                cancel: &mut FnMut() -> bool) -> Result<Vec<u8>, ZipError> {
+        let aes_field = match format::AesExtraField::from_extra_field(&entry.header.extra_field) {
+            Some(aes_field) => aes_field,
+            None => return Err(ZipError::DecompressionFailure(MaybeUtf8Buf::new()))
+        };
+        let password = match self.options.password {
+            Some(ref password) => password.clone(),
+            None => return Err(ZipError::Encrypted)
+        };
+        // the whole entry has to be read, as the authentication code is at its end
+        try!(self.reader.seek(SeekFrom::Start(entry.data_pos)));
+        let encrypted = try!(self.reader.read_vec(entry.compressed_size as usize));
+        let data = try!(aes::decrypt(aes_field.strength, &password[..], &encrypted[..]));
+
+        let len = cmp::min(wish_len, entry.uncompressed_size as usize);
+        // AE-2 leaves crc32 zeroed and relies on the authentication code alone
+        let crc32 = if len == entry.uncompressed_size as usize && aes_field.vendor_version == 1 {
+            entry.crc32
+        } else {
+            0
         };
-        if crc32 != 0 && crc32 != crc32::crc32(&bytes){
-            return Err(ZipError::CrcError);
+        decode(data, len, aes_field.compression_method, crc32, entry.uncompressed_size,
+               self.options.keep_partial_output, cancel)
+    }
+
+    // reads `len` bytes of entry data at `pos` and decodes them into `out_len` bytes
+    fn extract_block(&mut self, pos: u64, len: usize, out_len: usize, method: u16, crc32: u32,
+                     limit: u64, cancel: &mut FnMut() -> bool) -> Result<Vec<u8>, ZipError> {
+        try!(self.reader.seek(SeekFrom::Start(pos)));
+        let compressed = try!(self.reader.read_vec(len));
+        decode(compressed, out_len, method, crc32, limit, self.options.keep_partial_output, cancel)
+    }
+}
+
+impl<R:Read+Seek+Clone+Send+'static> ZipReader<R> {
+    /// Extracts every entry under `dest` like `extract_to` does, spreading the entries over
+    /// `threads` threads. Each thread reads from its own clone of the source, so this is
+    /// meant for sources that don't share a position between clones, like an archive in
+    /// memory. When entries fail, the error of the first thread (in entry order) is returned;
+    /// the other threads still extract their share. Pays off for archives with many small
+    /// entries, which spend most of their time decompressing rather than writing.
+    pub fn extract_parallel(&mut self, dest: &Path, threads: usize) -> Result<(), ZipError> {
+        let infos: Vec<FileInfo> = try!(self.try_files().collect());
+        // directories are created up front, so that the threads only have files to write
+        let mut jobs = Vec::new();
+        for info in infos.into_iter() {
+            let target = match info.sanitized_path() {
+                Some(path) => dest.join(&path),
+                None => continue
+            };
+            if info.is_dir() {
+                try!(fs::create_dir_all(&target));
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                try!(fs::create_dir_all(parent));
+            }
+            jobs.push((info, target));
+        }
+        if jobs.is_empty() {
+            return Ok(());
+        }
+        let threads = cmp::max(1, cmp::min(threads, jobs.len()));
+        let per_thread = (jobs.len() + threads - 1) / threads;
+        let mut handles = Vec::new();
+        for part in jobs.chunks(per_thread) {
+            let part = part.to_vec();
+            let mut worker = self.worker();
+            handles.push(thread::spawn(move || -> Result<(), ZipError> {
+                for &(ref info, ref target) in part.iter() {
+                    let bytes = try!(worker.read(info, usize::MAX));
+                    let mut file = try!(File::create(target));
+                    try!(file.write_all(&bytes[..]));
+                }
+                Ok(())
+            }));
+        }
+        let mut result = Ok(());
+        for handle in handles.into_iter() {
+            match handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => if result.is_ok() { result = Err(e); },
+                Err(payload) => panic::resume_unwind(payload)
+            }
+        }
+        result
+    }
+
+    // a reader of its own for a thread, sharing nothing but the end record and the options
+    fn worker(&self) -> ZipReader<R> {
+        ZipReader {
+            reader: self.reader.clone(),
+            end_record: self.end_record.clone(),
+            archive_size: self.archive_size,
+            archive_start: self.archive_start,
+            zip64_record: self.zip64_record.clone(),
+            options: self.options.clone(),
+            file_cache: None,
+            data_offsets: HashMap::new(),
+            header_offsets: HashMap::new(),
+        }
+    }
+}
+
+/// A forward-only reader for archives arriving from a source that can't seek, like a pipe
+/// or a socket. Entries are read from their local file headers in file order and the central
+/// directory is never looked at, so entries deleted or replaced by a later update of the
+/// archive still show up.
+///
+/// Entries whose sizes follow them in a data descriptor are only found if the descriptor
+/// starts with its (optional) signature, which is what most writers emit.
+/// Iteration stops at the first error.
+pub struct StreamingZipReader<R> {
+    reader: BufReader<R>,
+    offset: u64,
+    done: bool,
+}
+
+impl<R:Read> StreamingZipReader<R> {
+    pub fn new(reader: R) -> StreamingZipReader<R> {
+        StreamingZipReader { reader: BufReader::new(reader), offset: 0, done: false }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(FileInfo, EntryReader)>, ZipError> {
+        let sig = try!(self.reader.read_le_u32());
+        if sig == format::CDH_SIGNATURE || sig == format::EOCDR_SIGNATURE {
+            // past the last entry
+            return Ok(None);
+        }
+        if sig != format::LFH_SIGNATURE {
+            return Err(ZipError::InvalidSignature(sig, Some(self.offset)));
+        }
+        let header = try!(format::LocalFileHeader::read_after_signature(&mut self.reader));
+        try!(header.check_supported());
+        if header.is_encrypted() {
+            return Err(ZipError::Encrypted);
+        }
+        let mut info = FileInfo::from_local_header(&header, self.offset);
+        self.offset += header.total_size();
+
+        let data = if header.has_data_descriptor() {
+            let (data, dd) = try!(read_until_data_descriptor(&mut self.reader, header.is_zip64(),
+                                                             &mut self.offset));
+            info.crc32 = dd.crc32;
+            info.compressed_size = dd.compressed_size;
+            info.uncompressed_size = dd.uncompressed_size;
+            data
+        } else {
+            // from the Zip64 extra field if the local header defers to it
+            let data = try!(self.reader.read_vec(info.compressed_size as usize));
+            self.offset += data.len() as u64;
+            data
+        };
+
+        let bytes = try!(decode(data, info.uncompressed_size as usize, header.compression_method,
+                                info.crc32, info.uncompressed_size, false, &mut || false)
+                         .map_err(|e| e.with_name(&info.name)));
+        Ok(Some((info, EntryReader { inner: Cursor::new(bytes) })))
+    }
+}
+
+// reads entry data up to a data descriptor, taking the first signature followed by
+// a compressed size that matches the number of bytes read so far; `offset` is advanced
+// past the descriptor
+fn read_until_data_descriptor<T:Read>(r: &mut T, zip64: bool,
+                                      offset: &mut u64) -> Result<(Vec<u8>, format::DataDescriptor), ZipError> {
+    let descriptor_len = if zip64 { 24 } else { 16 };
+    let mut buf = Vec::new();
+    let mut start = 0;
+    loop {
+        while buf.len() < start + descriptor_len {
+            buf.push(try!(r.read_byte()));
+        }
+        let dd = try!(format::DataDescriptor::read(&mut Cursor::new(&buf[start..]), zip64));
+        if dd.signature_present && dd.compressed_size == start as u64 {
+            *offset += buf.len() as u64;
+            buf.truncate(start);
+            return Ok((buf, dd));
+        }
+        start += 1;
+    }
+}
+
+impl<R:Read> Iterator for StreamingZipReader<R> {
+    type Item = Result<(FileInfo, EntryReader), ZipError>;
+
+    fn next(&mut self) -> Option<Result<(FileInfo, EntryReader), ZipError>> {
+        if self.done {
+            return None;
+        }
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => { self.done = true; None },
+            Err(e) => { self.done = true; Some(Err(e)) }
+        }
+    }
+}
+
+#[cfg(windows)]
+static NEWLINE: &'static [u8] = b"\r\n";
+#[cfg(not(windows))]
+static NEWLINE: &'static [u8] = b"\n";
+
+// replaces the CRLF, CR and LF line endings in `data` with `newline`
+fn convert_line_endings(data: &[u8], newline: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'\r' if data.get(i + 1) == Some(&b'\n') => { out.extend(newline.iter().cloned()); i += 1; },
+            b'\r' | b'\n' => out.extend(newline.iter().cloned()),
+            b => out.push(b)
         }
-        Ok(bytes[0..len].to_vec())
+        i += 1;
+    }
+    out
+}
+
+// UTF-8 if it is, code page 437 otherwise
+fn detect_name_encoding(name: &[u8]) -> MaybeUtf8Buf {
+    match String::from_utf8(name.to_vec()) {
+        Ok(s) => MaybeUtf8Buf::from_str(s),
+        Err(_) => MaybeUtf8Buf::from_str(cp437::decode(name))
+    }
+}
+
+static COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+// the most `read_into` reserves up front; larger entries grow the buffer as they are extracted
+static MAX_RESERVED_SIZE: u64 = 64 * 1024 * 1024;
+
+// fills in where a signature was expected, which the format readers don't know
+fn at_offset(e: ZipError, offset: u64) -> ZipError {
+    match e {
+        ZipError::InvalidSignature(sig, None) => ZipError::InvalidSignature(sig, Some(offset)),
+        e => e
+    }
+}
+
+// a zero crc32 means the data is not checked, as for partial reads;
+// decompressing to more than `limit` bytes is an error
+fn decode(mut data: Vec<u8>, out_len: usize, method: u16, crc32: u32, limit: u64,
+          keep_partial: bool, cancel: &mut FnMut() -> bool) -> Result<Vec<u8>, ZipError> {
+    match CompressionMethod::from_u16(method) {
+        CompressionMethod::Store   => {
+            if crc32 != 0 && crc32 != crc32::crc32(&data) {
+                return Err(ZipError::CrcError(MaybeUtf8Buf::new()));
+            }
+            data.truncate(out_len);
+            Ok(data)
+        },
+        CompressionMethod::Deflate => decompress(data, out_len, crc32, limit, keep_partial, cancel),
+        CompressionMethod::Deflate64 => decompress_deflate64(data, out_len, crc32, limit, keep_partial, cancel),
+        CompressionMethod::Zstd    => decompress_zstd(data, out_len, crc32, limit),
+        method => Err(ZipError::UnsupportedCompressionMethod(method.to_u16()))
+    }
+}
+
+// the inflater stops past `len` bytes, which is below `limit` for partial reads; the
+// output is a prefix of the whole one, so it still can't pass `limit` unless the stream does
+fn decompress(data: Vec<u8>, len: usize, crc32: u32, limit: u64, keep_partial: bool,
+              cancel: &mut FnMut() -> bool) -> Result<Vec<u8>, ZipError> {
+    let mut bytes = Vec::new();
+    if let Err(e) = inflate::inflate(&data[..], false, cmp::min(len as u64, limit), &mut bytes, cancel) {
+        return Err(inflate_failure(e, bytes, len, keep_partial));
+    }
+    if bytes.len() as u64 > limit {
+        return Err(ZipError::DecompressedSizeLimitExceeded);
+    }
+    check_decompressed(&bytes, len, crc32, limit)
+}
+
+#[cfg(feature = "deflate64")]
+fn decompress_deflate64(data: Vec<u8>, len: usize, crc32: u32, limit: u64, keep_partial: bool,
+                        cancel: &mut FnMut() -> bool) -> Result<Vec<u8>, ZipError> {
+    let mut bytes = Vec::new();
+    if let Err(e) = inflate::inflate(&data[..], true, cmp::min(len as u64, limit), &mut bytes, cancel) {
+        return Err(inflate_failure(e, bytes, len, keep_partial));
+    }
+    if bytes.len() as u64 > limit {
+        return Err(ZipError::DecompressedSizeLimitExceeded);
+    }
+    check_decompressed(&bytes, len, crc32, limit)
+}
+
+#[cfg(not(feature = "deflate64"))]
+fn decompress_deflate64(_data: Vec<u8>, _len: usize, _crc32: u32, _limit: u64, _keep_partial: bool,
+                        _cancel: &mut FnMut() -> bool) -> Result<Vec<u8>, ZipError> {
+    Err(ZipError::UnsupportedCompressionMethod(CompressionMethod::Deflate64.to_u16()))
+}
+
+#[cfg(feature = "zstd")]
+fn decompress_zstd(data: Vec<u8>, len: usize, crc32: u32, limit: u64) -> Result<Vec<u8>, ZipError> {
+    let decoder = match zstd::Decoder::new(&data[..]) {
+        Ok(decoder) => decoder,
+        Err(_) => return Err(ZipError::DecompressionFailure(MaybeUtf8Buf::new()))
+    };
+    // as with the inflater, decoding stops one byte past `len` (or `limit`), which is
+    // enough to tell a stream that goes on from one that ends there
+    let mut bytes = Vec::new();
+    let stop = cmp::min(len as u64, limit).saturating_add(1);
+    if decoder.take(stop).read_to_end(&mut bytes).is_err() {
+        return Err(ZipError::DecompressionFailure(MaybeUtf8Buf::new()));
+    }
+    if bytes.len() as u64 > limit {
+        return Err(ZipError::DecompressedSizeLimitExceeded);
     }
+    check_decompressed(&bytes, len, crc32, limit)
 }
 
+#[cfg(not(feature = "zstd"))]
+fn decompress_zstd(_data: Vec<u8>, _len: usize, _crc32: u32, _limit: u64) -> Result<Vec<u8>, ZipError> {
+    Err(ZipError::UnsupportedCompressionMethod(CompressionMethod::Zstd.to_u16()))
+}
+
+fn is_directory_entry(info: &FileInfo) -> bool {
+    info.is_dir() || (info.uncompressed_size == 0 && info.name.ends_with_char('\\'))
+}
+
+static FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes.iter() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// "a.txt" becomes "a_1.txt", "README" becomes "README_1"
+fn numbered_name(name: &str, n: usize) -> String {
+    match name.rfind('.') {
+        Some(pos) if pos > 0 => format!("{}_{}{}", &name[..pos], n, &name[pos..]),
+        _ => format!("{}_{}", name, n)
+    }
+}
+
+fn normalize_separator(b: u8) -> u8 {
+    if b == b'\\' { b'/' } else { b }
+}
+
+// compares names taking `\` and `/` as the same separator
+fn separator_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() &&
+        a.iter().zip(b.iter()).all(|(&x, &y)| normalize_separator(x) == normalize_separator(y))
+}
+
+fn normalize_name_byte(b: u8) -> u8 {
+    normalize_separator(b).to_ascii_lowercase()
+}
+
+// compares names folding ASCII case and taking `\` and `/` as the same separator
+fn normalized_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() &&
+        a.iter().zip(b.iter()).all(|(&x, &y)| normalize_name_byte(x) == normalize_name_byte(y))
+}
+
+// the output inflated before the error is at most the `len` bytes asked for
+fn inflate_failure(e: inflate::InflateError, mut partial: Vec<u8>, len: usize, keep_partial: bool) -> ZipError {
+    match e {
+        inflate::InflateError::Cancelled => return ZipError::Cancelled,
+        inflate::InflateError::Write(e) => return ZipError::IoError(e),
+        inflate::InflateError::Invalid => {}
+    }
+    if !keep_partial {
+        return ZipError::DecompressionFailure(MaybeUtf8Buf::new());
+    }
+    partial.truncate(len);
+    ZipError::PartiallyDecompressed(MaybeUtf8Buf::new(), partial)
+}
+
+// `limit` is the declared uncompressed size, which a full extraction has to produce exactly
+fn check_decompressed(bytes: &[u8], len: usize, crc32: u32, limit: u64) -> Result<Vec<u8>, ZipError> {
+    if len as u64 == limit && bytes.len() != len {
+        return Err(ZipError::DecompressionFailure(MaybeUtf8Buf::new()));
+    }
+    if crc32 != 0 && crc32 != crc32::crc32(bytes) {
+        return Err(ZipError::CrcError(MaybeUtf8Buf::new()));
+    }
+    Ok(bytes[0..cmp::min(len, bytes.len())].to_vec())
+}
@@ -1,9 +1,2448 @@
-mod crc32;
+use std::cmp;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::io::{Cursor, Read, Write, BufRead, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use maybe_utf8::{MaybeUtf8Buf, MaybeUtf8Slice};
+use names::MaybeUtf8Ext;
+use flate;
+use crc32;
+use error::ZipError;
+use fileinfo::{self, CompressionMethod, FileInfo, HostOs};
+use format;
+use util::{ReadExt, WriteExt};
+use reader::{ZipReader, ZipReaderOptions, StreamingZipReader};
+use writer::{ZipWriter, FileOptions};
+
+fn open_bytes(bytes: &[u8]) -> ZipReader<Cursor<Vec<u8>>> {
+    ZipReader::from_bytes(bytes.to_vec()).unwrap()
+}
+
+// a scratch directory, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(prefix: &str) -> io::Result<TempDir> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("{}-{}-{}", prefix, process::id(), n));
+        try!(fs::create_dir_all(&path));
+        Ok(TempDir(path))
+    }
+
+    fn path(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// an entry for `build_archive`; tests may tweak the headers to craft broken archives
+struct TestEntry {
+    local: format::LocalFileHeader,
+    central: format::CentralDirectoryHeader,
+    data: Vec<u8>,
+}
+
+fn entry(name: &str, method: u16, contents: &[u8]) -> TestEntry {
+    let data = if method == 8 { flate::deflate_bytes(contents).to_vec() } else { contents.to_vec() };
+
+    let mut local = format::LocalFileHeader::new();
+    local.version_needed_to_extract = 20;
+    local.compression_method = method;
+    local.crc32 = crc32::crc32(contents);
+    local.compressed_size = data.len() as u32;
+    local.uncompressed_size = contents.len() as u32;
+    local.file_name = MaybeUtf8Buf::from_str(name.to_string());
+
+    let mut central = format::CentralDirectoryHeader::new();
+    central.version_made_by = 20;
+    central.version_needed_to_extract = 20;
+    central.compression_method = method;
+    central.crc32 = local.crc32;
+    central.compressed_size = local.compressed_size;
+    central.uncompressed_size = local.uncompressed_size;
+    central.file_name = local.file_name.clone();
+
+    TestEntry { local: local, central: central, data: data }
+}
+
+fn build_archive(entries: Vec<TestEntry>) -> Vec<u8> {
+    let mut w = Vec::new();
+    let mut centrals = Vec::new();
+    for mut e in entries.into_iter() {
+        e.central.relative_offset_of_local_header = w.len() as u32;
+        e.local.write(&mut w).unwrap();
+        w.write_all(&e.data).unwrap();
+        centrals.push(e.central);
+    }
+
+    let mut end = format::EndOfCentralDirectoryRecord::new();
+    end.entry_count_this_disk = centrals.len() as u16;
+    end.total_entry_count = centrals.len() as u16;
+    end.central_directory_offset = w.len() as u32;
+    for central in centrals.iter() {
+        central.write(&mut w).unwrap();
+    }
+    end.central_directory_size = w.len() as u32 - end.central_directory_offset;
+    end.write(&mut w).unwrap();
+    w
+}
 
 #[test]
 fn test() {
-    let vec = ~[0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39];
-    assert_eq!(crc32::crc32(vec), 0xcbf43926u32);
+    let vec = [0x31u8, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39];
+    assert_eq!(crc32::crc32(&vec), 0xcbf43926u32);
+}
+
+// the AES archives hold a single "secret.txt" encrypted with the password "password":
+// aes128.zip uses AE-1 over a stored entry, aes192.zip and aes256.zip use AE-2 over deflate.
+fn check_aes_archive(bytes: &[u8]) {
+    let mut zip = open_bytes(bytes);
+    zip.set_password(b"password");
+    let info = zip.info("secret.txt").unwrap();
+    assert!(info.is_encrypted);
+    let mut out = Vec::new();
+    zip.extract_file(&info, &mut out).unwrap();
+
+    let mut expected = Vec::new();
+    for _ in 0..4 {
+        expected.extend(b"This entry is encrypted with WinZip AES.\n".iter().cloned());
+    }
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn aes128() {
+    check_aes_archive(include_bytes!("../../tests/data/aes128.zip"));
+}
+
+#[test]
+fn aes192() {
+    check_aes_archive(include_bytes!("../../tests/data/aes192.zip"));
+}
+
+#[test]
+fn aes256() {
+    check_aes_archive(include_bytes!("../../tests/data/aes256.zip"));
+}
+
+#[test]
+fn aes_wrong_password() {
+    let mut zip = open_bytes(include_bytes!("../../tests/data/aes256.zip"));
+    zip.set_password(b"wrong");
+    let info = zip.info("secret.txt").unwrap();
+    let mut out = Vec::new();
+    assert!(match zip.extract_file(&info, &mut out) {
+        Err(ZipError::InvalidPassword) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn unix_extra_field_skips_unknown_versions() {
+    let extra = [0x75u8, 0x78, 3, 0, 2, 0xaa, 0xbb,               // version 2, unknown layout
+                 0x75, 0x78, 11, 0, 1, 4, 0xe8, 3, 0, 0, 4, 0xe9, 3, 0, 0]; // uid 1000, gid 1001
+    assert_eq!(format::UnixExtraField::from_extra_field(&extra),
+               Some(format::UnixExtraField::V1 { uid: 1000, gid: 1001 }));
+    assert_eq!(format::UnixExtraField::from_extra_field(&extra[..7]),
+               Some(format::UnixExtraField::Unknown { version: 2, data: vec![0xaa, 0xbb] }));
+}
+
+#[test]
+fn unzip_one() {
+    let contents = b"hello hello hello hello hello hello hello\n";
+    let bytes = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 8, contents)]);
+    let mut zip = open_bytes(&bytes);
+    let mut out = Vec::new();
+    assert_eq!(zip.unzip_one("b.txt", &mut out).unwrap(), contents.len() as u64);
+    assert_eq!(out, contents.to_vec());
+}
+
+#[test]
+fn encrypted_without_password() {
+    let mut e = entry("locked.txt", 0, b"not really encrypted");
+    e.local.general_purpose_bit_flag = 1;
+    e.central.general_purpose_bit_flag = 1;
+    let mut zip = open_bytes(&build_archive(vec![e]));
+    let info = zip.info("locked.txt").unwrap();
+    let mut out = Vec::new();
+    assert!(match zip.extract_file(&info, &mut out) {
+        Err(ZipError::Encrypted) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn traditional_encryption() {
+    // ZipCrypto sets the encryption flag without changing the compression method
+    let mut e = entry("locked.txt", 0, b"not really encrypted");
+    e.local.general_purpose_bit_flag = 1;
+    e.central.general_purpose_bit_flag = 1;
+    let options = ZipReaderOptions::new().password(b"secret");
+    let mut zip = ZipReader::with_options(Cursor::new(build_archive(vec![e])), options).unwrap();
+    let info = zip.info("locked.txt").unwrap();
+    assert!(match zip.extract_file(&info, &mut Vec::new()) {
+        Err(ZipError::UnsupportedEncryption) => true,
+        _ => false
+    });
+    assert!(zip.verify(&info).is_err());
+    // the header itself can still be read
+    assert_eq!(zip.read_raw(&info).unwrap(), b"not really encrypted".to_vec());
+    assert!(zip.data_offset(&info).is_ok());
+}
+
+#[test]
+fn entry_reader_read_until() {
+    let bytes = build_archive(vec![entry("lines.txt", 8, b"first line\nsecond line\n")]);
+    let mut zip = open_bytes(&bytes);
+    let info = zip.info("lines.txt").unwrap();
+    let mut r = zip.open_entry(&info).unwrap();
+    let mut line = Vec::new();
+    r.read_until(b'\n', &mut line).unwrap();
+    assert_eq!(line, b"first line\n".to_vec());
+    line.clear();
+    r.read_until(b'\n', &mut line).unwrap();
+    assert_eq!(line, b"second line\n".to_vec());
+    assert_eq!(r.read_until(b'\n', &mut line).unwrap(), 0);
+}
+
+#[test]
+fn into_path_map() {
+    let bytes = build_archive(vec![entry("dir/", 0, b""),
+                                   entry("dir/sub/file.txt", 8, b"nested contents"),
+                                   entry("top.txt", 0, b"top")]);
+    let map = open_bytes(&bytes).into_path_map().unwrap();
+    assert_eq!(map.len(), 2);
+    let nested = Path::new("dir").join("sub").join("file.txt");
+    assert_eq!(map[&nested], b"nested contents".to_vec());
+    assert_eq!(map[&Path::new("top.txt").to_path_buf()], b"top".to_vec());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_round_trip() {
+    use zstd;
+
+    let contents = b"zstandard zstandard zstandard zstandard zstandard\n";
+    let mut e = entry("data.zst.txt", 0, contents);
+    e.data = zstd::encode_all(&contents[..], 3).unwrap();
+    e.local.compression_method = 93;
+    e.local.compressed_size = e.data.len() as u32;
+    e.central.compression_method = 93;
+    e.central.compressed_size = e.data.len() as u32;
+
+    let mut zip = open_bytes(&build_archive(vec![e]));
+    let info = zip.info("data.zst.txt").unwrap();
+    assert_eq!(info.compression_method, CompressionMethod::Zstd);
+    let mut out = Vec::new();
+    zip.extract_file(&info, &mut out).unwrap();
+    assert_eq!(out, contents.to_vec());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_size_limit() {
+    use zstd;
+
+    // a little data that decodes to far more than the entry says
+    let contents = vec![0u8; 10 * 1024 * 1024];
+    let mut e = entry("bomb.zst", 0, b"tiny");
+    e.data = zstd::encode_all(&contents[..], 3).unwrap();
+    e.local.compression_method = 93;
+    e.local.compressed_size = e.data.len() as u32;
+    e.central.compression_method = 93;
+    e.central.compressed_size = e.data.len() as u32;
+
+    let mut zip = open_bytes(&build_archive(vec![e]));
+    let info = zip.info("bomb.zst").unwrap();
+    assert!(match zip.extract_file(&info, &mut Vec::new()) {
+        Err(ZipError::DecompressedSizeLimitExceeded) => true,
+        _ => false
+    });
+}
+
+// packs Deflate bits for hand-made streams: values least significant bit first, and
+// Huffman codes most significant bit first
+struct BitWriter {
+    bytes: Vec<u8>,
+    count: usize,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, n: usize) {
+        for i in 0..n {
+            if self.count % 8 == 0 {
+                self.bytes.push(0);
+            }
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= (((value >> i) & 1) as u8) << (self.count % 8);
+            self.count += 1;
+        }
+    }
+
+    fn code(&mut self, code: u32, n: usize) {
+        for i in (0..n).rev() {
+            self.bits(code >> i, 1);
+        }
+    }
+}
+
+#[test]
+fn deflate_matches_after_flushing() {
+    // a fixed Huffman block of 144 literals repeated by matches of distance 144, past the
+    // output the inflater holds before writing it out, then one that reaches back 28944 bytes
+    let mut w = BitWriter { bytes: Vec::new(), count: 0 };
+    w.bits(1, 1);
+    w.bits(1, 2);
+    for b in 0..144 {
+        w.code(0x30 + b, 8);
+    }
+    for _ in 0..1200 {
+        w.code(0xc5, 8);
+        w.code(14, 5);
+        w.bits(144 - 129, 6);
+    }
+    w.code(0xc5, 8);
+    w.code(29, 5);
+    w.bits(28944 - 24577, 13);
+    w.code(0, 7);
+
+    let contents: Vec<u8> = (0..144 + 1201 * 258).map(|i| (i % 144) as u8).collect();
+    let mut e = entry("long.bin", 8, &contents[..]);
+    e.data = w.bytes.clone();
+    e.local.compressed_size = e.data.len() as u32;
+    e.central.compressed_size = e.data.len() as u32;
+
+    let mut zip = open_bytes(&build_archive(vec![e]));
+    let info = zip.info("long.bin").unwrap();
+    let mut out = Vec::new();
+    zip.extract_file(&info, &mut out).unwrap();
+    assert!(out == contents);
+    zip.verify(&info).unwrap();
+}
+
+#[cfg(feature = "deflate64")]
+#[test]
+fn deflate64_entry() {
+    // a fixed Huffman block with matches only Deflate64 has: a length of 40000 through
+    // length code 285's 16 extra bits, then a distance of 40002 through distance code 30
+    let mut w = BitWriter { bytes: Vec::new(), count: 0 };
+    w.bits(1, 1);
+    w.bits(1, 2);
+    w.code(0x30 + b'b' as u32, 8);
+    w.code(0x30 + b'a' as u32, 8);
+    w.code(0xc5, 8);
+    w.bits(40000 - 3, 16);
+    w.code(0, 5);
+    w.code(0xc5, 8);
+    w.bits(0, 16);
+    w.code(30, 5);
+    w.bits(40002 - 32769, 14);
+    w.code(0, 7);
+
+    let mut contents = b"ba".to_vec();
+    contents.extend(vec![b'a'; 40000]);
+    contents.extend_from_slice(b"baa");
+
+    let deflate64_entry = |name: &str| {
+        let mut e = entry(name, 0, &contents[..]);
+        e.data = w.bytes.clone();
+        e.local.compression_method = 9;
+        e.local.compressed_size = e.data.len() as u32;
+        e.central.compression_method = 9;
+        e.central.compressed_size = e.data.len() as u32;
+        e
+    };
+    let mut broken = deflate64_entry("broken.bin");
+    broken.local.crc32 ^= 1;
+    broken.central.crc32 ^= 1;
+
+    let mut zip = open_bytes(&build_archive(vec![deflate64_entry("data.bin"), broken]));
+    let info = zip.info("data.bin").unwrap();
+    assert_eq!(info.compression_method, CompressionMethod::Deflate64);
+    let mut out = Vec::new();
+    zip.extract_file(&info, &mut out).unwrap();
+    assert_eq!(out, contents);
+
+    let info = zip.info("broken.bin").unwrap();
+    assert!(match zip.extract_file(&info, &mut Vec::new()) { Err(ZipError::CrcError(..)) => true, _ => false });
+}
+
+#[test]
+fn deflate_length_code_285() {
+    // in plain Deflate, length code 285 means 258 with no extra bits
+    let mut w = BitWriter { bytes: Vec::new(), count: 0 };
+    w.bits(1, 1);
+    w.bits(1, 2);
+    w.code(0x30 + b'a' as u32, 8);
+    w.code(0xc5, 8);
+    w.code(0, 5);
+    w.code(0, 7);
+
+    let contents = vec![b'a'; 259];
+    let mut e = entry("a.bin", 8, &contents[..]);
+    e.data = w.bytes.clone();
+    e.local.compressed_size = e.data.len() as u32;
+    e.central.compressed_size = e.data.len() as u32;
+
+    // and distance code 30 doesn't exist, even with more than 32768 bytes to reach back to
+    let mut w = BitWriter { bytes: Vec::new(), count: 0 };
+    w.bits(1, 1);
+    w.bits(1, 2);
+    w.code(0x30 + b'a' as u32, 8);
+    for _ in 0..128 {
+        w.code(0xc5, 8);
+        w.code(0, 5);
+    }
+    w.code(0xc5, 8);
+    w.code(30, 5);
+    w.bits(0, 14);
+    w.code(0, 7);
+
+    let mut far = entry("far.bin", 8, &vec![b'a'; 1 + 129 * 258][..]);
+    far.data = w.bytes.clone();
+    far.local.compressed_size = far.data.len() as u32;
+    far.central.compressed_size = far.data.len() as u32;
+
+    let mut zip = open_bytes(&build_archive(vec![e, far]));
+    let info = zip.info("a.bin").unwrap();
+    let mut out = Vec::new();
+    zip.extract_file(&info, &mut out).unwrap();
+    assert_eq!(out, contents);
+
+    let info = zip.info("far.bin").unwrap();
+    assert!(match zip.extract_file(&info, &mut Vec::new()) {
+        Err(ZipError::DecompressionFailure(..)) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn truncated_deflate_partial_output() {
+    let contents: Vec<u8> = (0..4000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut e = entry("cut.bin", 8, &contents[..]);
+    let cut = e.data.len() * 3 / 4;
+    e.data.truncate(cut);
+    e.local.compressed_size = cut as u32;
+    e.central.compressed_size = cut as u32;
+    let bytes = build_archive(vec![e]);
+
+    let mut zip = open_bytes(&bytes);
+    let info = zip.info("cut.bin").unwrap();
+    assert!(match zip.extract_file(&info, &mut Vec::new()) {
+        Err(ZipError::DecompressionFailure(..)) => true,
+        _ => false
+    });
+
+    let options = ZipReaderOptions::new().keep_partial_output(true);
+    let mut zip = ZipReader::with_options(Cursor::new(bytes), options).unwrap();
+    let info = zip.info("cut.bin").unwrap();
+    // when the entry is decompressed into memory, as for its first bytes, the error carries them
+    match zip.extract_first(&info, contents.len(), &mut Vec::new()) {
+        Err(ZipError::PartiallyDecompressed(name, partial)) => {
+            assert_eq!(name, info.name);
+            assert!(partial.len() > 0 && partial.len() < contents.len());
+            assert_eq!(&partial[..], &contents[..partial.len()]);
+        }
+        _ => panic!("expected the partial contents")
+    }
+
+    // extracting into a writer has written the partial contents by the time it fails
+    let mut out = Vec::new();
+    match zip.extract_file(&info, &mut out) {
+        Err(ZipError::PartiallyDecompressed(_, partial)) => assert!(partial.is_empty()),
+        _ => panic!("expected a partial decompression")
+    }
+    assert!(out.len() > 0 && out.len() < contents.len());
+    assert_eq!(&out[..], &contents[..out.len()]);
+}
+
+#[test]
+fn compression_method_round_trip() {
+    for &code in [0u16, 8, 9, 14, 93, 0xffff].iter() {
+        assert_eq!(CompressionMethod::from_u16(code).to_u16(), code);
+    }
+    assert_eq!(CompressionMethod::from_u16(14), CompressionMethod::Unknown(14));
+
+    let mut e = entry("lzma.bin", 0, b"pretend this is LZMA");
+    e.central.compression_method = 14;
+    let mut zip = open_bytes(&build_archive(vec![e]));
+    let info = zip.info("lzma.bin").unwrap();
+    assert_eq!(info.compression_method.to_u16(), 14);
+}
+
+#[test]
+fn fixed_version_made_by_is_reproducible() {
+    // the same inputs written as if on a Unix and on an MS-DOS host, then pinned to one value
+    let build = |host_version: u16, fixed: Option<u16>| {
+        let mut w = ZipWriter::new(Vec::new());
+        w.set_version_made_by(host_version);
+        if let Some(version) = fixed {
+            w.set_version_made_by(version);
+        }
+        w.start_file("a.txt", CompressionMethod::Store).unwrap();
+        w.write_all(b"same contents").unwrap();
+        w.finish().unwrap()
+    };
+    assert!(build((3 << 8) | 20, None) != build(20, None));
+    assert_eq!(build((3 << 8) | 20, Some(20)), build(20, Some(20)));
+
+    let bytes = build(20, Some(20));
+    let mut out = Vec::new();
+    assert_eq!(open_bytes(&bytes).unzip_one("a.txt", &mut out).unwrap(), 13);
+}
+
+#[test]
+fn common_prefix() {
+    let bytes = build_archive(vec![entry("proj/", 0, b""),
+                                   entry("proj/a.txt", 0, b"a"),
+                                   entry("proj/src/b.rs", 0, b"b")]);
+    assert_eq!(open_bytes(&bytes).common_prefix().unwrap().unwrap(), "proj");
+
+    let bytes = build_archive(vec![entry("proj/a.txt", 0, b"a"), entry("other/b.txt", 0, b"b")]);
+    assert!(open_bytes(&bytes).common_prefix().unwrap().is_none());
+
+    let bytes = build_archive(vec![entry("proj/a.txt", 0, b"a"), entry("README", 0, b"b")]);
+    assert!(open_bytes(&bytes).common_prefix().unwrap().is_none());
+}
+
+#[test]
+fn unsupported_compression_method() {
+    let mut e = entry("lzma.bin", 0, b"pretend this is LZMA");
+    e.local.compression_method = 14;
+    e.central.compression_method = 14;
+    let mut zip = open_bytes(&build_archive(vec![e]));
+    let info = zip.info("lzma.bin").unwrap();
+    let mut out = Vec::new();
+    assert!(match zip.extract_file(&info, &mut out) {
+        Err(ZipError::UnsupportedCompressionMethod(14)) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn total_uncompressed_size() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"12345"),
+                                   entry("b.txt", 8, b"1234567890"),
+                                   entry("empty", 0, b"")]);
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.entry_count(), 3);
+    assert_eq!(zip.total_uncompressed_size().unwrap(), 15);
+
+    // a Zip64 entry counts with its real size, which doesn't fit in the central header
+    let mut big = entry("big", 0, b"");
+    big.central.uncompressed_size = 0xffffffff;
+    big.central.extra_field = format::zip64_extra_field(&[0x100000000]);
+    let bytes = build_archive(vec![entry("a.txt", 0, b"12345"), big]);
+    assert_eq!(open_bytes(&bytes).total_uncompressed_size().unwrap(), 0x100000005);
+}
+
+#[test]
+fn zip64_data_descriptor() {
+    let bytes = vec![0x50u8, 0x4b, 0x07, 0x08,                  // signature
+                     0x78, 0x56, 0x34, 0x12,                    // crc32
+                     0x10, 0, 0, 0, 1, 0, 0, 0,                 // compressed size
+                     0x20, 0, 0, 0, 1, 0, 0, 0];                // uncompressed size
+    let dd = format::DataDescriptor::read(&mut Cursor::new(bytes), true).unwrap();
+    assert!(dd.signature_present);
+    assert_eq!(dd.crc32, 0x12345678);
+    assert_eq!(dd.compressed_size, 0x100000010);
+    assert_eq!(dd.uncompressed_size, 0x100000020);
+
+    let bytes = vec![0x78u8, 0x56, 0x34, 0x12, 5, 0, 0, 0, 7, 0, 0, 0];
+    let dd = format::DataDescriptor::read(&mut Cursor::new(bytes), false).unwrap();
+    assert!(!dd.signature_present);
+    assert_eq!((dd.compressed_size, dd.uncompressed_size), (5, 7));
+}
+
+#[test]
+fn try_files_on_truncated_directory() {
+    let mut bytes = build_archive(vec![entry("a.txt", 0, b"a"), entry("b.txt", 0, b"b")]);
+    // claim a third entry in the end of central directory record
+    let end = bytes.len() - 22;
+    bytes[end + 8] = 3;
+    bytes[end + 10] = 3;
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.file_names().size_hint(), (0, Some(3)));
+    assert_eq!(zip.file_names().count(), 2);
+    let results: Vec<_> = zip.try_files().collect();
+    assert_eq!(results.len(), 3);
+    assert!(results[0].is_ok() && results[1].is_ok());
+    assert!(match results[2] {
+        Err(ZipError::InvalidSignature(sig, _)) => sig == format::EOCDR_SIGNATURE,
+        _ => false
+    });
+}
+
+#[test]
+fn cached_central_directory() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"a"), entry("b.txt", 8, b"bbbbbbbb")]);
+    let mut zip = open_bytes(&bytes);
+    let first: Vec<_> = zip.file_names().collect();
+    zip.get_mut().seek(SeekFrom::Start(3)).unwrap();
+    let second: Vec<_> = zip.file_names().collect();
+    assert_eq!(first, second);
+    assert_eq!(zip.by_index(1).unwrap().name, "b.txt");
+    assert!(zip.by_index(2).is_err());
+
+    zip.reload().unwrap();
+    assert_eq!(zip.files().count(), 2);
+}
+
+#[test]
+fn read_to_string_stripping_bom() {
+    let bytes = build_archive(vec![entry("bom.txt", 8, b"\xef\xbb\xbfkey=value\r\n"),
+                                   entry("plain.txt", 0, b"key=value\r\n")]);
+    let mut zip = open_bytes(&bytes);
+    for name in ["bom.txt", "plain.txt"].iter() {
+        let info = zip.info(*name).unwrap();
+        let mut r = zip.open_entry(&info).unwrap();
+        assert_eq!(r.read_to_string_stripping_bom().unwrap(), "key=value\r\n");
+    }
+}
+
+#[test]
+fn archive_size() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"a"), entry("b.txt", 8, b"bbbbbbbb")]);
+    assert_eq!(open_bytes(&bytes).archive_size(), bytes.len() as u64);
+}
+
+#[test]
+fn case_insensitive_and_normalized_lookup() {
+    let bytes = build_archive(vec![entry("Folder/File.TXT", 0, b"a"), entry("dir\\file", 0, b"b")]);
+    let mut zip = open_bytes(&bytes);
+    assert!(zip.info("folder/file.txt").is_err());
+    assert_eq!(zip.info_ignore_case("folder/file.txt").unwrap().name, "Folder/File.TXT");
+    assert!(zip.info_ignore_case("dir/file").is_err());
+    assert_eq!(zip.find_normalized("DIR/File").unwrap().name, "dir\\file");
+    assert_eq!(zip.find_normalized("folder\\file.txt").unwrap().name, "Folder/File.TXT");
+}
+
+#[test]
+fn files_with_prefix() {
+    let bytes = build_archive(vec![entry("foo/", 0, b""),
+                                   entry("foo/a.txt", 0, b"a"),
+                                   entry("foo\\sub\\b.txt", 0, b"b"),
+                                   entry("foobar/c.txt", 0, b"c"),
+                                   entry("d.txt", 0, b"d")]);
+    let mut zip = open_bytes(&bytes);
+    let names = |files: Vec<FileInfo>| -> Vec<String> {
+        files.into_iter().map(|f| f.name.into_str_lossy()).collect()
+    };
+    assert_eq!(names(zip.files_with_prefix("foo").unwrap()), vec!["foo/", "foo/a.txt", "foo\\sub\\b.txt"]);
+    assert_eq!(names(zip.files_with_prefix("foo\\sub").unwrap()), vec!["foo\\sub\\b.txt"]);
+    assert_eq!(zip.files_with_prefix("").unwrap().len(), 5);
+    assert!(zip.files_with_prefix("fo").unwrap().is_empty());
+}
+
+#[test]
+fn add_file_from_path() {
+    let dir = TempDir::new("zip-test").unwrap();
+    let path = dir.path().join("source.txt");
+    let contents = b"read straight from the file system\n";
+    File::create(&path).unwrap().write_all(contents).unwrap();
+
+    let mut w = ZipWriter::new(Vec::new());
+    w.add_file_from_path("source.txt", &path, CompressionMethod::Store).unwrap();
+    let bytes = w.finish().unwrap();
+
+    let mut out = Vec::new();
+    open_bytes(&bytes).unzip_one("source.txt", &mut out).unwrap();
+    assert_eq!(out, contents.to_vec());
+
+    // stored files are copied over with the crc32 and sizes in the local header
+    let contents: Vec<u8> = (0..200000).map(|i| (i % 251) as u8).collect();
+    File::create(&path).unwrap().write_all(&contents).unwrap();
+    let mut w = ZipWriter::new(Vec::new());
+    w.add_file_from_path("big.bin", &path, CompressionMethod::Store).unwrap();
+    w.add_file_from_path("deflated.bin", &path, CompressionMethod::Deflate).unwrap();
+    let bytes = w.finish().unwrap();
+    let local = format::LocalFileHeader::read(&mut &bytes[..]).unwrap();
+    assert!(!local.has_data_descriptor());
+    assert_eq!((local.crc32, local.compressed_size), (crc32::crc32(&contents), contents.len() as u32));
+    let next = format::LocalFileHeader::read(&mut &bytes[30 + 7 + contents.len()..]).unwrap();
+    assert_eq!(next.file_name, "deflated.bin");
+    let mut zip = open_bytes(&bytes);
+    for name in ["big.bin", "deflated.bin"].iter() {
+        let f = zip.info(*name).unwrap();
+        assert_eq!(f.crc32, crc32::crc32(&contents));
+        let mut out = Vec::new();
+        zip.extract_file(&f, &mut out).unwrap();
+        assert!(out == contents);
+    }
+    let mut streamed = StreamingZipReader::new(NonSeekable(Cursor::new(bytes.clone())));
+    let (info, mut r) = streamed.next().unwrap().unwrap();
+    assert_eq!(info.uncompressed_size, contents.len() as u64);
+    let mut out = Vec::new();
+    r.read_to_end(&mut out).unwrap();
+    assert!(out == contents);
+}
+
+#[test]
+fn extract_flattened() {
+    let bytes = build_archive(vec![entry("a/", 0, b""),
+                                   entry("a/x.txt", 0, b"first x"),
+                                   entry("b/c/x.txt", 8, b"second x"),
+                                   entry("b/y", 0, b"y")]);
+    let dir = TempDir::new("zip-test").unwrap();
+    open_bytes(&bytes).extract_flattened(dir.path()).unwrap();
+    let read = |name: &str| {
+        let mut v = Vec::new();
+        File::open(dir.path().join(name)).unwrap().read_to_end(&mut v).unwrap();
+        v
+    };
+    assert_eq!(read("x.txt"), b"first x".to_vec());
+    assert_eq!(read("x_1.txt"), b"second x".to_vec());
+    assert_eq!(read("y"), b"y".to_vec());
+    assert!(!dir.path().join("a").exists());
+}
+
+#[test]
+fn strict_header_check() {
+    let mut e = entry("a.txt", 0, b"contents");
+    e.central.crc32 = 0xdeadbeef;
+    let bytes = build_archive(vec![e]);
+    let mut zip = open_bytes(&bytes);
+    let info = zip.info("a.txt").unwrap();
+    let mut out = Vec::new();
+    assert!(match zip.extract_file(&info, &mut out) {
+        Err(ZipError::HeaderMismatch) => false,
+        _ => true
+    });
+    zip.set_strict(true);
+    assert!(match zip.extract_file(&info, &mut out) {
+        Err(ZipError::HeaderMismatch) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn zip_error_from_io_error() {
+    use std::error::Error;
+    let e: ZipError = From::from(io::Error::new(io::ErrorKind::UnexpectedEof, "end of file"));
+    assert!(match e { ZipError::IoError(ref e) => e.kind() == io::ErrorKind::UnexpectedEof, _ => false });
+    assert!(e.cause().is_some());
+    assert!(ZipError::CrcError(MaybeUtf8Buf::new()).cause().is_none());
+}
+
+#[test]
+fn signature_inside_comment() {
+    let mut bytes = build_archive(vec![entry("a.txt", 0, b"contents")]);
+    // a comment holding what looks like a whole end of central directory record
+    let mut comment = vec![0x50u8, 0x4b, 0x05, 0x06];
+    comment.extend([0u8; 18].iter().cloned());
+    comment.extend(b"trailing text".iter().cloned());
+    let len = bytes.len();
+    bytes[len - 2] = comment.len() as u8;
+    bytes.extend(comment.into_iter());
+
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.entry_count(), 1);
+    let mut out = Vec::new();
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"contents".to_vec());
+}
+
+#[test]
+fn cancelled_extraction() {
+    let bytes = build_archive(vec![entry("dir/a.txt", 0, b"first"),
+                                   entry("dir/b.txt", 8, b"second")]);
+    let dir = TempDir::new("zip-cancel").unwrap();
+    let mut zip = open_bytes(&bytes);
+    let mut checks = 0;
+    let r = zip.extract_to(dir.path(), || { checks += 1; checks > 1 });
+    assert!(match r { Err(ZipError::Cancelled) => true, _ => false });
+    assert!(dir.path().join("dir/a.txt").exists());
+    assert!(!dir.path().join("dir/b.txt").exists());
+
+    assert!(zip.test_archive(|| false).is_ok());
+    assert!(match zip.test_archive(|| true) { Err(ZipError::Cancelled) => true, _ => false });
+
+    // large entries are cancelled partway through, a chunk of stored data or 64 KB of
+    // inflated data after the check before the entry
+    let contents: Vec<u8> = (0..300000u32).map(|i| (i % 253) as u8).collect();
+    let bytes = build_archive(vec![entry("stored.bin", 0, &contents), entry("deflated.bin", 8, &contents)]);
+    let mut zip = open_bytes(&bytes);
+    let mut checks = 0;
+    let r = zip.extract_to(dir.path(), || { checks += 1; checks > 2 });
+    assert!(match r { Err(ZipError::Cancelled) => true, _ => false });
+    assert_eq!(checks, 3);
+    assert!(!dir.path().join("stored.bin").exists());
+
+    // one check before each entry and four within the stored one, so the seventh comes
+    // from the inflater
+    let mut checks = 0;
+    let r = zip.test_archive(|| { checks += 1; checks > 6 });
+    assert!(match r { Err(ZipError::Cancelled) => true, _ => false });
+    assert_eq!(checks, 7);
+}
+
+#[test]
+fn truncated_headers_give_io_errors() {
+    let e = entry("a.txt", 0, b"contents");
+    let mut w = Vec::new();
+    e.local.write(&mut w).unwrap();
+    let local = w;
+    let mut r = Cursor::new(local[..local.len() - 3].to_vec());
+    assert!(match format::LocalFileHeader::read(&mut r) { Err(ZipError::IoError(_)) => true, _ => false });
+
+    let mut w = Vec::new();
+    e.central.write(&mut w).unwrap();
+    let central = w;
+    let mut r = Cursor::new(central[..10].to_vec());
+    assert!(match format::CentralDirectoryHeader::read(&mut r) { Err(ZipError::IoError(_)) => true, _ => false });
+
+    let mut r = Cursor::new(vec![0x50, 0x4b, 0x05, 0x06, 0, 0]);
+    assert!(match format::EndOfCentralDirectoryRecord::read(&mut r) { Err(ZipError::IoError(_)) => true, _ => false });
+}
+
+// hides the Seek impl of the wrapped reader
+struct NonSeekable<R>(R);
+
+impl<R: Read> Read for NonSeekable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.0.read(buf) }
+}
+
+#[test]
+fn streaming_reader() {
+    let contents = b"streamed without knowing the size up front";
+    let mut e = entry("b.txt", 8, contents);
+    e.local.general_purpose_bit_flag |= 8;
+    e.central.general_purpose_bit_flag |= 8;
+    let mut dd = Vec::new();
+    dd.write_le_u32(format::DD_SIGNATURE).unwrap();
+    dd.write_le_u32(e.local.crc32).unwrap();
+    dd.write_le_u32(e.local.compressed_size).unwrap();
+    dd.write_le_u32(e.local.uncompressed_size).unwrap();
+    e.local.crc32 = 0;
+    e.local.compressed_size = 0;
+    e.local.uncompressed_size = 0;
+    e.data.extend(dd.into_iter());
+    let bytes = build_archive(vec![entry("a.txt", 0, b"first"), e]);
+
+    let mut zip = StreamingZipReader::new(NonSeekable(Cursor::new(bytes)));
+    let (info, mut r) = zip.next().unwrap().unwrap();
+    assert_eq!(info.name, "a.txt");
+    let mut v = Vec::new();
+    r.read_to_end(&mut v).unwrap();
+    assert_eq!(v, b"first".to_vec());
+    let (info, mut r) = zip.next().unwrap().unwrap();
+    assert_eq!(info.name, "b.txt");
+    assert_eq!(info.uncompressed_size as usize, contents.len());
+    let mut v = Vec::new();
+    r.read_to_end(&mut v).unwrap();
+    assert_eq!(v, contents.to_vec());
+    assert!(zip.next().is_none());
+}
+
+#[test]
+fn streaming_zip64_and_encrypted() {
+    let mut e = entry("a.txt", 0, b"contents");
+    e.local.compressed_size = 0xffffffff;
+    e.local.uncompressed_size = 0xffffffff;
+    e.local.extra_field = zip64_extra(8, 8);
+    let mut locked = entry("locked.txt", 0, b"not really encrypted");
+    locked.local.general_purpose_bit_flag = 1;
+    locked.central.general_purpose_bit_flag = 1;
+    let bytes = build_archive(vec![e, locked]);
+
+    let mut zip = StreamingZipReader::new(NonSeekable(Cursor::new(bytes)));
+    let (info, mut r) = zip.next().unwrap().unwrap();
+    assert_eq!((info.compressed_size, info.uncompressed_size), (8, 8));
+    let mut v = Vec::new();
+    r.read_to_end(&mut v).unwrap();
+    assert_eq!(v, b"contents".to_vec());
+    // ZipCrypto is reported rather than taken for missing support of AES
+    assert!(match zip.next() {
+        Some(Err(ZipError::UnsupportedEncryption)) => true,
+        _ => false
+    });
+    assert!(zip.next().is_none());
+}
+
+#[test]
+fn multi_disk_archive() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"a"), entry("b.txt", 0, b"b")]);
+    let mut spanned = bytes.clone();
+    // the last of three disks, holding one of the two entries
+    let end = spanned.len() - 22;
+    spanned[end + 4] = 2;
+    spanned[end + 8] = 1;
+    let r = ZipReader::new(Cursor::new(spanned));
+    assert!(match r { Err(ZipError::UnsupportedMultiDisk) => true, _ => false });
+    assert!(ZipReader::new(Cursor::new(bytes)).is_ok());
+}
+
+#[test]
+fn read_raw() {
+    let contents = b"compressible compressible compressible compressible";
+    let bytes = build_archive(vec![entry("a.txt", 8, contents)]);
+    let mut zip = open_bytes(&bytes);
+    let info = zip.info("a.txt").unwrap();
+    let raw = zip.read_raw(&info).unwrap();
+    assert_eq!(raw.len(), info.compressed_size as usize);
+    assert!(raw.len() < contents.len());
+    assert_eq!(flate::inflate_bytes(&raw[..]).unwrap().to_vec(), contents.to_vec());
+}
+
+#[test]
+fn read_raw_bounds() {
+    // the raw data is checked to lie before the central directory like extracted data
+    let bytes = build_archive(vec![entry("a.txt", 0, b"contents")]);
+    let mut zip = open_bytes(&bytes);
+    let info = zip.info("a.txt").unwrap();
+    assert_eq!(zip.read_raw(&info).unwrap(), b"contents".to_vec());
+    let mut f = info.clone();
+    f.local_file_header_offset = zip.central_directory_offset();
+    assert!(match zip.read_raw(&f) {
+        Err(ZipError::InvalidOffset(..)) => true,
+        _ => false
+    });
+
+    let mut e = entry("a.txt", 0, b"contents");
+    e.local.compressed_size = 1000;
+    let bytes = build_archive(vec![e]);
+    assert!(match open_bytes(&bytes).read_raw(&info) {
+        Err(ZipError::InvalidOffset(0)) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn copy_from() {
+    let contents = b"deflated deflated deflated deflated deflated";
+    let bytes = build_archive(vec![entry("keep.txt", 8, contents), entry("drop.txt", 0, b"dropped")]);
+    let mut src = open_bytes(&bytes);
+    let info = src.info("keep.txt").unwrap();
+
+    let mut w = ZipWriter::new(Vec::new());
+    w.copy_from(&mut src, &info).unwrap();
+    let copied = w.finish().unwrap();
+
+    let mut zip = open_bytes(&copied);
+    assert_eq!(zip.entry_count(), 1);
+    assert!(zip.info("drop.txt").is_err());
+    let copy = zip.info("keep.txt").unwrap();
+    assert_eq!(copy.compression_method, CompressionMethod::Deflate);
+    assert_eq!((copy.crc32, copy.compressed_size), (info.crc32, info.compressed_size));
+    let mut out = Vec::new();
+    zip.extract_file(&copy, &mut out).unwrap();
+    assert_eq!(out, contents.to_vec());
+
+    // a name that isn't UTF-8, a Unix mode, a comment and a version needed of 6.3 for Zstd,
+    // whose data is copied without being looked at
+    let mut e = entry("", 93, b"not really zstd");
+    e.local.file_name = MaybeUtf8Buf::from_bytes(b"caf\xe9.sh".to_vec());
+    e.local.version_needed_to_extract = 63;
+    e.central.file_name = e.local.file_name.clone();
+    e.central.version_needed_to_extract = 63;
+    e.central.version_made_by = (3 << 8) | 30;
+    e.central.external_file_attributes = 0o100755 << 16;
+    e.central.file_comment = MaybeUtf8Buf::from_str("a comment".to_string());
+    let bytes = build_archive(vec![e]);
+    let mut src = open_bytes(&bytes);
+    let info = src.files().next().unwrap();
+    let mut w = ZipWriter::new_in_memory();
+    w.copy_from(&mut src, &info).unwrap();
+    let copied = w.finish_into_bytes().unwrap();
+    let mut zip = open_bytes(&copied);
+    let copy = zip.files().next().unwrap();
+    assert_eq!(copy.name.as_bytes(), b"caf\xe9.sh");
+    assert_eq!(copy.unix_mode(), Some(0o100755));
+    assert_eq!((copy.version_made_by, copy.version_needed_to_extract), ((3 << 8) | 30, 63));
+    assert_eq!(zip.read_raw(&copy).unwrap(), b"not really zstd".to_vec());
+    let h = zip.central_directory_header(&copy).unwrap();
+    assert_eq!(h.file_comment.as_bytes(), b"a comment");
+    assert!(!h.has_utf8_name());
 }
 
+#[test]
+fn copy_from_invalid_datetime() {
+    // a month and day of zero and hour 25, which `try_new` would refuse
+    let mut e = entry("a.txt", 0, b"a");
+    e.central.last_modified_datetime = format::MsdosDateTime::new(2015, 0, 0, 25, 0, 0);
+    let bytes = build_archive(vec![e]);
+    let mut src = open_bytes(&bytes);
+    let info = src.info("a.txt").unwrap();
+    let mut w = ZipWriter::new_in_memory();
+    w.copy_from(&mut src, &info).unwrap();
+    let copied = w.finish_into_bytes().unwrap();
+    let copy = open_bytes(&copied).info("a.txt").unwrap();
+    assert_eq!(copy.last_modified_datetime, (2015, 0, 0, 25, 0, 0));
+}
+
+#[test]
+fn file_info_to_path() {
+    let bytes = build_archive(vec![entry("dir/a.txt", 0, b"a"), entry("bad\0name", 0, b"b")]);
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.info("dir/a.txt").unwrap().to_path(), Some(Path::new("dir/a.txt").to_path_buf()));
+    assert_eq!(zip.info("bad\0name").unwrap().to_path(), None);
+}
+
+#[test]
+fn compression_ratio() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"hello"), entry("empty", 8, b"")]);
+    let mut zip = open_bytes(&bytes);
+    let stored = zip.info("a.txt").unwrap();
+    assert!(stored.is_stored());
+    assert_eq!(stored.compression_ratio(), 1.0);
+
+    let empty = zip.info("empty").unwrap();
+    assert!(!empty.is_stored());
+    assert_eq!(empty.uncompressed_size, 0);
+    assert_eq!(empty.compression_ratio(), 1.0);
+
+    let mut f = stored.clone();
+    f.compressed_size = 25;
+    f.uncompressed_size = 100;
+    assert_eq!(f.compression_ratio(), 0.25);
+}
+
+#[test]
+fn entries() {
+    let bytes = build_archive(vec![entry("dir/", 0, b""),
+                                   entry("dir/a.txt", 0, b"first"),
+                                   entry("dir/b.txt", 8, b"second")]);
+    let mut zip = open_bytes(&bytes);
+    let entries: Vec<(FileInfo, Vec<u8>)> = zip.entries().map(|e| e.unwrap()).collect();
+    assert_eq!(entries.len(), 3);
+    assert!(entries[0].0.is_dir() && entries[0].1.is_empty());
+    assert_eq!(entries[1].0.name, "dir/a.txt");
+    assert_eq!(entries[1].1, b"first".to_vec());
+    assert_eq!(entries[2].0.name, "dir/b.txt");
+    assert_eq!(entries[2].1, b"second".to_vec());
+}
+
+#[test]
+fn max_decompressed_size() {
+    let contents = [b'x'; 100];
+    let bytes = build_archive(vec![entry("a.txt", 8, &contents)]);
+    let mut zip = open_bytes(&bytes);
+    let info = zip.info("a.txt").unwrap();
+    zip.set_max_decompressed_size(10);
+    let mut out = Vec::new();
+    assert!(match zip.extract_file(&info, &mut out) {
+        Err(ZipError::DecompressedSizeLimitExceeded) => true,
+        _ => false
+    });
+
+    // a stream inflating past the size its headers declare
+    let mut e = entry("bomb.txt", 8, &contents);
+    e.local.uncompressed_size = 4;
+    e.central.uncompressed_size = 4;
+    let bytes = build_archive(vec![e]);
+    let mut zip = open_bytes(&bytes);
+    let info = zip.info("bomb.txt").unwrap();
+    assert!(match zip.extract_file(&info, &mut out) {
+        Err(ZipError::DecompressedSizeLimitExceeded) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn inflated_size_shorter_than_declared() {
+    let contents = [b'y'; 50];
+    let mut e = entry("short.txt", 8, &contents);
+    e.local.uncompressed_size = 100;
+    e.central.uncompressed_size = 100;
+    let bytes = build_archive(vec![e]);
+    let mut zip = open_bytes(&bytes);
+    let info = zip.info("short.txt").unwrap();
+    let mut out = Vec::new();
+    assert!(match zip.extract_file(&info, &mut out) {
+        Err(ZipError::DecompressionFailure(..)) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn partial_read_past_inflated_size() {
+    let contents = [b'z'; 50];
+    let mut e = entry("short.txt", 8, &contents);
+    e.local.uncompressed_size = 100;
+    e.central.uncompressed_size = 100;
+    let bytes = build_archive(vec![e]);
+    let mut zip = open_bytes(&bytes);
+    let info = zip.info("short.txt").unwrap();
+    let mut out = Vec::new();
+    zip.extract_first(&info, 80, &mut out).unwrap();
+    assert_eq!(out, contents.to_vec());
+
+    zip.set_strict(true);
+    let mut out = Vec::new();
+    assert!(match zip.extract_first(&info, 80, &mut out) {
+        Err(ZipError::DecompressionFailure(..)) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn extract_first_stops_inflating() {
+    // a non-final stored block, then a block of the reserved type, which only a full
+    // extraction gets to
+    let contents: Vec<u8> = (0..20u8).collect();
+    let mut data = vec![0, 20, 0, 0xeb, 0xff];
+    data.extend_from_slice(&contents);
+    data.push(0x07);
+
+    let mut e = entry("big.bin", 8, &contents);
+    e.data = data;
+    e.local.compressed_size = e.data.len() as u32;
+    e.central.compressed_size = e.data.len() as u32;
+    e.local.uncompressed_size = 1000000;
+    e.central.uncompressed_size = 1000000;
+    let mut zip = open_bytes(&build_archive(vec![e]));
+    let info = zip.info("big.bin").unwrap();
+    let mut out = Vec::new();
+    zip.extract_first(&info, 10, &mut out).unwrap();
+    assert_eq!(out, contents[..10].to_vec());
+
+    assert!(match zip.extract_file(&info, &mut Vec::new()) {
+        Err(ZipError::DecompressionFailure(..)) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn append_to_archive() {
+    let bytes = build_archive(vec![entry("a.txt", 8, b"already there")]);
+    let reader = open_bytes(&bytes);
+    let mut w = ZipWriter::new_append(Cursor::new(bytes.clone()), reader).unwrap();
+    w.start_file("b.txt", CompressionMethod::Store).unwrap();
+    w.write_all(b"appended").unwrap();
+    let appended = w.finish().unwrap().into_inner();
+
+    let mut zip = open_bytes(&appended);
+    assert_eq!(zip.entry_count(), 2);
+    let mut out = Vec::new();
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"already there".to_vec());
+    let mut out = Vec::new();
+    zip.unzip_one("b.txt", &mut out).unwrap();
+    assert_eq!(out, b"appended".to_vec());
+}
+
+#[test]
+fn write_deflate() {
+    let contents = [b'z'; 10000];
+    let mut w = ZipWriter::new(Cursor::new(Vec::new()));
+    w.start_file("z.txt", CompressionMethod::Deflate).unwrap();
+    w.write_all(&contents).unwrap();
+    let bytes = w.finish().unwrap().into_inner();
+
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("z.txt").unwrap();
+    assert_eq!(f.compression_method, CompressionMethod::Deflate);
+    assert_eq!(f.uncompressed_size, 10000);
+    assert!(f.compressed_size < f.uncompressed_size);
+    let mut out = Vec::new();
+    zip.extract_file(&f, &mut out).unwrap();
+    assert_eq!(out, contents.to_vec());
+}
+
+#[test]
+fn write_unsupported_method() {
+    let mut w = ZipWriter::new_in_memory();
+    w.start_file("a.txt", CompressionMethod::Store).unwrap();
+    w.write_all(b"still open").unwrap();
+    assert!(match w.start_file("b.zst", CompressionMethod::Zstd) {
+        Err(ZipError::UnsupportedCompressionMethod(93)) => true,
+        _ => false
+    });
+    assert!(match w.start_file_with_options("c.bin", FileOptions::new().compression_method(CompressionMethod::Unknown(14))) {
+        Err(ZipError::UnsupportedCompressionMethod(14)) => true,
+        _ => false
+    });
+    // nothing was written, and the entry before is still the one being written
+    w.write_all(b" and more").unwrap();
+    let bytes = w.finish_into_bytes().unwrap();
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.files().len(), 1);
+    let mut out = Vec::new();
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"still open and more".to_vec());
+}
+
+#[test]
+fn msdos_date_time_range_check() {
+    let valid = format::MsdosDateTime::try_new(2015, 6, 30, 23, 59, 58).unwrap();
+    assert_eq!(valid.to_tuple(), format::MsdosDateTime::new(2015, 6, 30, 23, 59, 58).to_tuple());
+    assert!(format::MsdosDateTime::try_new(1980, 1, 1, 0, 0, 0).is_ok());
+    assert!(format::MsdosDateTime::try_new(2107, 12, 31, 0, 0, 0).is_ok());
+    assert!(format::MsdosDateTime::try_new(2016, 2, 29, 0, 0, 0).is_ok());
+    assert!(format::MsdosDateTime::try_new(2000, 2, 29, 0, 0, 0).is_ok());
+
+    let invalid = [(1979, 12, 31, 23, 59, 58), (2108, 1, 1, 0, 0, 0),
+                   (2015, 0, 1, 0, 0, 0), (2015, 13, 1, 0, 0, 0),
+                   (2015, 1, 0, 0, 0, 0), (2015, 1, 40, 0, 0, 0), (2015, 1, 32, 0, 0, 0),
+                   (2015, 2, 29, 0, 0, 0), (2015, 2, 30, 0, 0, 0), (2016, 2, 30, 0, 0, 0),
+                   (2100, 2, 29, 0, 0, 0), (2015, 4, 31, 0, 0, 0), (2015, 11, 31, 0, 0, 0),
+                   (2015, 1, 1, 24, 0, 0), (2015, 1, 1, 0, 60, 0),
+                   (2015, 1, 1, 0, 0, 59)];
+    for &(year, month, day, hour, minute, second) in invalid.iter() {
+        let r = format::MsdosDateTime::try_new(year, month, day, hour, minute, second);
+        assert!(match r { Err(ZipError::InvalidDateTime) => true, _ => false });
+    }
+}
 
+#[test]
+fn msdos_date_time_seconds() {
+    let t = format::MsdosDateTime::new(2015, 3, 1, 14, 30, 44);
+    let mut bytes = Vec::new();
+    t.write(&mut bytes).unwrap();
+    let t = format::MsdosDateTime::read(&mut Cursor::new(bytes)).unwrap();
+    assert_eq!(t.to_tuple(), (2015, 3, 1, 14, 30, 44));
+}
+
+#[test]
+fn msdos_date_time_round_trip() {
+    for hour in 0..24 {
+        for minute in 0..60 {
+            for second in 0..60 {
+                let t = format::MsdosDateTime::new(2000, 1, 1, hour, minute, second);
+                assert_eq!((t.hour(), t.minute(), t.second()), (hour, minute, second & !1));
+            }
+        }
+    }
+}
+
+#[test]
+fn msdos_date_time_unix_time() {
+    let t = format::MsdosDateTime::new(2015, 3, 1, 14, 30, 44);
+    assert_eq!(t.to_unix_time(), 1425220244);
+    assert_eq!(format::MsdosDateTime::new(1980, 1, 1, 0, 0, 0).to_unix_time(), 315532800);
+    assert_eq!(format::MsdosDateTime::new(2107, 12, 31, 23, 59, 58).to_unix_time(), 4354819198);
+
+    assert_eq!(format::MsdosDateTime::from_unix_time(1425220244).unwrap().to_tuple(),
+               (2015, 3, 1, 14, 30, 44));
+    assert_eq!(format::MsdosDateTime::from_unix_time(1425220245).unwrap().to_tuple(),
+               (2015, 3, 1, 14, 30, 44));
+    assert!(format::MsdosDateTime::from_unix_time(315532799).is_err());
+    assert!(format::MsdosDateTime::from_unix_time(4354819200).is_err());
+    assert!(format::MsdosDateTime::from_unix_time(-1).is_err());
+
+    let bytes = build_archive(vec![entry("a.txt", 0, b"a")]);
+    let mut zip = open_bytes(&bytes);
+    let mut f = zip.info("a.txt").unwrap();
+    f.last_modified_datetime = (2015, 3, 1, 14, 30, 44);
+    assert_eq!(f.last_modified_unix_time(), 1425220244);
+}
+
+#[test]
+fn write_in_memory() {
+    let mut w = ZipWriter::new_in_memory();
+    w.start_file("a.txt", CompressionMethod::Deflate).unwrap();
+    w.write_all(b"built in memory").unwrap();
+    w.start_file("b.txt", CompressionMethod::Store).unwrap();
+    w.write_all(b"b").unwrap();
+    let bytes = w.finish_into_bytes().unwrap();
+
+    let mut zip = ZipReader::from_bytes(bytes).unwrap();
+    assert_eq!(zip.files().len(), 2);
+    let mut out = Vec::new();
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"built in memory".to_vec());
+}
+
+#[test]
+fn write_stored_data_descriptor() {
+    let mut w = ZipWriter::new_in_memory();
+    w.start_file("known.txt", CompressionMethod::Store).unwrap();
+    w.write_all(b"sizes up front").unwrap();
+    let options = FileOptions::new().compression_method(CompressionMethod::Store).data_descriptor(true);
+    w.start_file_with_options("streamed.txt", options).unwrap();
+    w.write_all(b"sizes after the data").unwrap();
+    let bytes = w.finish_into_bytes().unwrap();
+
+    // stored entries give their crc32 and sizes in the local header unless asked not to
+    let local = format::LocalFileHeader::read(&mut &bytes[..]).unwrap();
+    assert!(!local.has_data_descriptor());
+    assert_eq!((local.crc32, local.uncompressed_size), (crc32::crc32(b"sizes up front"), 14));
+    let second = 30 + 9 + 14;
+    let local = format::LocalFileHeader::read(&mut &bytes[second..]).unwrap();
+    assert!(local.has_data_descriptor());
+    assert_eq!((local.crc32, local.uncompressed_size), (0, 0));
+    let dd = format::DataDescriptor::read(&mut &bytes[second + 30 + 12 + 20..], false).unwrap();
+    assert_eq!((dd.crc32, dd.compressed_size), (crc32::crc32(b"sizes after the data"), 20));
+
+    let mut out = Vec::new();
+    open_bytes(&bytes).unzip_one("streamed.txt", &mut out).unwrap();
+    assert_eq!(out, b"sizes after the data".to_vec());
+    let mut streamed = StreamingZipReader::new(NonSeekable(Cursor::new(bytes)));
+    for &(name, contents) in [("known.txt", &b"sizes up front"[..]),
+                              ("streamed.txt", &b"sizes after the data"[..])].iter() {
+        let (info, mut r) = streamed.next().unwrap().unwrap();
+        assert_eq!(info.name, name);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, contents.to_vec());
+    }
+}
+
+#[test]
+fn write_unix_mode() {
+    let mut w = ZipWriter::new_in_memory();
+    w.set_version_made_by(20);
+    w.start_file_with_options("run.sh", FileOptions::new().unix_mode(0o100755)).unwrap();
+    w.write_all(b"#!/bin/sh\n").unwrap();
+    w.start_file_with_options("bin/", FileOptions::new().unix_mode(0o040755)).unwrap();
+    w.start_file("plain.txt", CompressionMethod::Store).unwrap();
+    let mut zip = ZipReader::from_bytes(w.finish_into_bytes().unwrap()).unwrap();
+
+    let f = zip.info("run.sh").unwrap();
+    assert_eq!(f.host_os(), HostOs::Unix);
+    assert_eq!(f.unix_mode(), Some(0o100755));
+    assert_eq!(f.compression_method, CompressionMethod::Deflate);
+    let f = zip.info("bin/").unwrap();
+    assert_eq!(f.unix_mode(), Some(0o040755));
+    assert_eq!(f.external_file_attributes & 0x10, 0x10);
+    let f = zip.info("plain.txt").unwrap();
+    assert_eq!(f.host_os(), HostOs::MsDos);
+    assert_eq!(f.unix_mode(), None);
+}
+
+#[test]
+fn write_last_modified() {
+    let mut w = ZipWriter::new_in_memory();
+    let options = FileOptions::new().last_modified_datetime(2015, 3, 1, 14, 30, 44);
+    w.start_file_with_options("a.txt", options).unwrap();
+    let options = FileOptions::new().last_modified_unix_time(1425220244);
+    w.start_file_with_options("b.txt", options).unwrap();
+    w.start_file("c.txt", CompressionMethod::Store).unwrap();
+    let options = FileOptions::new().last_modified_datetime(1979, 12, 31, 0, 0, 0);
+    assert!(match w.start_file_with_options("d.txt", options) {
+        Err(ZipError::InvalidDateTime) => true,
+        _ => false
+    });
+    let bytes = w.finish_into_bytes().unwrap();
+    let mut zip = open_bytes(&bytes);
+
+    assert_eq!(zip.info("a.txt").unwrap().last_modified_datetime, (2015, 3, 1, 14, 30, 44));
+    assert_eq!(zip.info("b.txt").unwrap().last_modified_datetime, (2015, 3, 1, 14, 30, 44));
+    assert_eq!(zip.info("c.txt").unwrap().last_modified_datetime,
+               format::MsdosDateTime::zero().to_tuple());
+    let f = zip.info("a.txt").unwrap();
+    let mut r = Cursor::new(bytes);
+    r.seek(SeekFrom::Start(f.local_file_header_offset as u64)).unwrap();
+    let local = format::LocalFileHeader::read(&mut r).unwrap();
+    assert_eq!(local.last_modified_datetime.to_tuple(), (2015, 3, 1, 14, 30, 44));
+}
+
+#[test]
+fn from_bytes() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"in memory")]);
+    let mut zip = ZipReader::from_bytes(bytes).unwrap();
+    let mut out = Vec::new();
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"in memory".to_vec());
+    assert!(match ZipReader::from_bytes(b"not a zip file".to_vec()) {
+        Err(ZipError::NotAZipFile) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn file_names_bytes() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"a"), entry("dir/b.txt", 0, b"b")]);
+    let mut zip = open_bytes(&bytes);
+    let first: Vec<*const u8> = zip.file_names_bytes().unwrap().map(|n| n.as_ptr()).collect();
+    let names: Vec<&[u8]> = zip.file_names_bytes().unwrap().collect();
+    assert_eq!(names, vec![&b"a.txt"[..], &b"dir/b.txt"[..]]);
+    // the names aren't copied, each call hands out the same cached bytes
+    let again: Vec<*const u8> = names.iter().map(|n| n.as_ptr()).collect();
+    assert_eq!(first, again);
+}
+
+#[test]
+fn contains() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"a"), entry("dir/b.txt", 0, b"b")]);
+    let mut zip = open_bytes(&bytes);
+    assert!(zip.contains("a.txt"));
+    assert!(zip.contains("dir/b.txt"));
+    assert!(!zip.contains("b.txt"));
+    assert!(zip.contains("dir\\b.txt"));
+    assert!(!zip.contains("A.TXT"));
+    assert!(!zip.contains("dir/B.txt"));
+
+    // an archive written on Windows, with backslashes in its names
+    let bytes = build_archive(vec![entry("dir\\sub\\c.txt", 0, b"c")]);
+    let mut zip = open_bytes(&bytes);
+    assert!(zip.contains("dir/sub/c.txt"));
+    assert!(zip.contains("dir\\sub/c.txt"));
+    assert!(zip.contains("dir\\sub\\c.txt"));
+    assert!(!zip.contains("dir/sub"));
+    assert!(!zip.contains("dir-sub-c.txt"));
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn unsupported_version() {
+    let mut e = entry("new.txt", 0, b"from the future");
+    e.local.version_needed_to_extract = 63;
+    e.central.version_needed_to_extract = 63;
+    let bytes = build_archive(vec![e, entry("old.txt", 0, b"fine")]);
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("new.txt").unwrap();
+    assert_eq!(f.requires_version(), 63);
+    let mut out = Vec::new();
+    assert!(match zip.extract_file(&f, &mut out) {
+        Err(ZipError::UnsupportedVersion(63)) => true,
+        _ => false
+    });
+    assert_eq!(zip.info("old.txt").unwrap().requires_version(), 20);
+    zip.unzip_one("old.txt", &mut out).unwrap();
+    assert_eq!(out, b"fine".to_vec());
+}
+
+#[test]
+fn format_listing() {
+    let mut a = entry("a.txt", 0, b"hello");
+    a.central.last_modified_datetime = format::MsdosDateTime::new(2015, 3, 1, 14, 30, 44);
+    let mut b = entry("", 0, &[b'x'; 200]);
+    b.central.file_name = MaybeUtf8Buf::from_bytes(vec![b'n', 0xff]);
+    b.central.compressed_size = 50;
+    let bytes = build_archive(vec![a, b]);
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.format_listing().unwrap(), concat!(
+        "    Length  Compressed  Ratio  Date       Time      Name\n",
+        "----------  ----------  -----  ---------- --------  ----\n",
+        "         5           5   100%  2015-03-01 14:30:44  a.txt\n",
+        "       200          50    25%  1980-00-00 00:00:00  n\u{fffd}\n",
+        "----------  ----------  -----                       ----\n",
+        "       205          55    27%                       2 file(s)\n"));
+}
+
+#[test]
+fn empty_archive() {
+    let bytes = build_archive(vec![]);
+    assert_eq!(bytes.len(), 22);
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.entry_count(), 0);
+    assert_eq!(zip.files().count(), 0);
+    assert_eq!(zip.try_files().count(), 0);
+    assert!(match zip.info("a.txt") { Err(ZipError::FileNotFoundInArchive(..)) => true, _ => false });
+    assert_eq!(zip.total_uncompressed_size().unwrap(), 0);
+}
+
+#[test]
+fn empty_entries() {
+    // an empty Deflate stream as most archivers write it: a single final fixed-Huffman block
+    let mut deflated = entry("deflated", 8, b"");
+    deflated.data = vec![0x03, 0x00];
+    deflated.local.compressed_size = 2;
+    deflated.central.compressed_size = 2;
+    let bytes = build_archive(vec![entry("stored", 0, b""), deflated]);
+    let mut zip = open_bytes(&bytes);
+    for name in ["stored", "deflated"].iter() {
+        let f = zip.info(*name).unwrap();
+        assert_eq!((f.crc32, f.uncompressed_size), (0, 0));
+        let mut out = vec![b'x'];
+        zip.unzip_one(*name, &mut out).unwrap();
+        assert_eq!(out, vec![b'x']);
+    }
+}
+
+#[test]
+fn reader_options() {
+    let mut e = entry("a.txt", 0, &[b'x'; 100]);
+    e.local.crc32 = 0;
+    let bytes = build_archive(vec![e, entry("b.txt", 0, b"small")]);
+    let options = ZipReaderOptions::new().strict(true).max_decompressed_size(10);
+    let mut zip = ZipReader::with_options(Cursor::new(bytes), options).unwrap();
+    let mut out = Vec::new();
+    assert!(match zip.unzip_one("a.txt", &mut out) {
+        Err(ZipError::HeaderMismatch) => true,
+        _ => false
+    });
+    zip.unzip_one("b.txt", &mut out).unwrap();
+    assert_eq!(out, b"small".to_vec());
+    zip.set_strict(false);
+    assert!(match zip.unzip_one("a.txt", &mut out) {
+        Err(ZipError::DecompressedSizeLimitExceeded) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn invalid_signature_offset() {
+    let mut bytes = build_archive(vec![entry("a.txt", 0, b"a"), entry("b.txt", 0, b"b")]);
+    let end = bytes.len() - 22;
+    let directory = bytes[end + 16] as usize;
+    let mut zip = open_bytes(&bytes);
+    let second = zip.by_index(1).unwrap();
+
+    // break the second central directory header, and the second local header
+    let second_cdh = directory + 46 + 5;
+    bytes[second_cdh] = b'X';
+    bytes[second.local_file_header_offset as usize] = b'X';
+    let mut zip = open_bytes(&bytes);
+    let results: Vec<_> = zip.try_files().collect();
+    assert!(match results[1] {
+        Err(ZipError::InvalidSignature(_, Some(offset))) => offset == second_cdh as u64,
+        _ => false
+    });
+    let mut out = Vec::new();
+    assert!(match zip.read_raw(&second) {
+        Err(ZipError::InvalidSignature(_, Some(offset))) => offset == second.local_file_header_offset as u64,
+        _ => false
+    });
+    let r = zip.extract_file(&second, &mut out);
+    assert_eq!(format!("{}", r.unwrap_err()),
+               format!("invalid ZIP signature 0x4034b58 at offset {}", second.local_file_header_offset));
+}
+
+#[test]
+fn recover() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 0, b"second")]);
+    let zip = open_bytes(&bytes);
+    let directory = zip.end_record().central_directory_offset as usize;
+
+    // cut off the central directory: both entries are whole
+    let files = ZipReader::recover(Cursor::new(bytes[..directory].to_vec())).unwrap();
+    let names: Vec<_> = files.iter().map(|f| f.name.as_bytes().to_vec()).collect();
+    assert_eq!(names, vec![b"a.txt".to_vec(), b"b.txt".to_vec()]);
+    assert_eq!(files[1].uncompressed_size, 6);
+
+    // cut off in the middle of the second entry's data
+    let files = ZipReader::recover(Cursor::new(bytes[..directory - 3].to_vec())).unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].name, "a.txt");
+
+    assert!(match ZipReader::recover(Cursor::new(b"not a zip file".to_vec())) {
+        Err(ZipError::NotAZipFile) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn is_text() {
+    let mut text = entry("a.txt", 0, b"text\r\n");
+    text.central.internal_file_attributes = 1;
+    let bytes = build_archive(vec![text, entry("b.bin", 0, &[0, 1, 2])]);
+    let mut zip = open_bytes(&bytes);
+    assert!(zip.info("a.txt").unwrap().is_text());
+    assert!(!zip.info("b.bin").unwrap().is_text());
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_file_text() {
+    let mut text = entry("a.txt", 0, b"one\r\ntwo\rthree\n");
+    text.central.internal_file_attributes = 1;
+    let binary = [0x0d, 0x0a, 0xff, 0x0d];
+    let bytes = build_archive(vec![text, entry("b.bin", 0, &binary)]);
+    let mut zip = open_bytes(&bytes);
+
+    let mut out = Vec::new();
+    let f = zip.info("a.txt").unwrap();
+    zip.extract_file_text(&f, &mut out, false).unwrap();
+    assert_eq!(out, b"one\ntwo\nthree\n".to_vec());
+
+    let f = zip.info("b.bin").unwrap();
+    let mut out = Vec::new();
+    zip.extract_file_text(&f, &mut out, false).unwrap();
+    assert_eq!(out, binary.to_vec());
+    let mut out = Vec::new();
+    zip.extract_file_text(&f, &mut out, true).unwrap();
+    assert_eq!(out, vec![0x0a, 0xff, 0x0a]);
+}
+
+#[test]
+fn digital_signature() {
+    let mut bytes = build_archive(vec![entry("a.txt", 0, b"a")]);
+    let mut zip = open_bytes(&bytes);
+    assert!(zip.digital_signature().unwrap().is_none());
+    let directory = zip.end_record().central_directory_offset as usize;
+    let end = bytes.len() - 22;
+    assert_eq!(zip.central_directory_bytes().unwrap(), bytes[directory..end].to_vec());
+
+    let mut record = Vec::new();
+    format::CentralDirectoryDigitalSignature { data_size: 3, data: vec![1, 2, 3] }
+        .write(&mut record).unwrap();
+    let eocdr = bytes.split_off(end);
+    bytes.extend(record.iter().cloned());
+    bytes.extend(eocdr.into_iter());
+    let size = (end - directory + record.len()) as u32;
+    (&mut bytes[end + record.len() + 12..]).write_le_u32(size).unwrap();
+
+    let mut zip = open_bytes(&bytes);
+    let signature = zip.digital_signature().unwrap().unwrap();
+    assert_eq!(signature.data, vec![1, 2, 3]);
+    let directory_bytes = zip.central_directory_bytes().unwrap();
+    assert_eq!(directory_bytes.len(), size as usize);
+    assert!(directory_bytes.ends_with(&record));
+    let mut out = Vec::new();
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"a".to_vec());
+}
+
+#[test]
+fn self_extractor_stub() {
+    let archive = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 8, b"second")]);
+    let mut bytes = vec![b'M'; 1024];
+    bytes.extend(archive.iter().cloned());
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.archive_start(), 1024);
+    assert_eq!(zip.info("a.txt").unwrap().local_file_header_offset, 1024);
+    let mut out = Vec::new();
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    zip.unzip_one("b.txt", &mut out).unwrap();
+    assert_eq!(out, b"firstsecond".to_vec());
+
+    // an archive without a stub, or one whose offsets were adjusted for it, is read as it is
+    assert_eq!(open_bytes(&archive).archive_start(), 0);
+}
+
+#[test]
+fn verify() {
+    let good = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 8, b"second")]);
+    let mut zip = open_bytes(&good);
+    let f = zip.info("a.txt").unwrap();
+    zip.verify(&f).unwrap();
+    assert!(zip.verify_all().unwrap().is_none());
+
+    let mut bad = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 0, b"second"),
+                                     entry("c.txt", 0, b"third")]);
+    let f = open_bytes(&bad).info("b.txt").unwrap();
+    let data = f.local_file_header_offset as usize + 30 + 5;
+    bad[data] = b'S';
+    let mut zip = open_bytes(&bad);
+    assert!(match zip.verify(&f) { Err(ZipError::CrcError(..)) => true, _ => false });
+    let (name, e) = zip.verify_all().unwrap().unwrap();
+    assert_eq!(name, "b.txt");
+    assert!(match e { ZipError::CrcError(..) => true, _ => false });
+}
+
+#[test]
+fn files_by_offset() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"a"), entry("b.txt", 0, b"b"),
+                                   entry("c.txt", 0, b"c")]);
+    // write the central directory in a different order than the entries
+    let mut zip = open_bytes(&bytes);
+    let mut headers = zip.central_directory_headers().unwrap();
+    headers.swap(0, 2);
+    headers.swap(0, 1);
+    let directory = zip.end_record().central_directory_offset as usize;
+    let mut shuffled = bytes[..directory].to_vec();
+    for h in headers.iter() {
+        h.write(&mut shuffled).unwrap();
+    }
+    shuffled.extend(bytes[bytes.len() - 22..].iter().cloned());
+
+    let mut zip = open_bytes(&shuffled);
+    let names: Vec<_> = zip.files().map(|f| f.name.as_bytes().to_vec()).collect();
+    assert_eq!(names, vec![b"b.txt".to_vec(), b"c.txt".to_vec(), b"a.txt".to_vec()]);
+    let files = zip.files_by_offset().unwrap();
+    let names: Vec<_> = files.iter().map(|f| f.name.as_bytes().to_vec()).collect();
+    assert_eq!(names, vec![b"a.txt".to_vec(), b"b.txt".to_vec(), b"c.txt".to_vec()]);
+    assert!(files.windows(2).all(|w| w[0].local_file_header_offset < w[1].local_file_header_offset));
+}
+
+#[test]
+fn error_entry_names() {
+    let mut bytes = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 0, b"second")]);
+    let f = open_bytes(&bytes).info("b.txt").unwrap();
+    bytes[f.local_file_header_offset as usize + 30 + 5] = b'S';
+    let mut zip = open_bytes(&bytes);
+    let mut out = Vec::new();
+    let e = zip.extract_file(&f, &mut out).unwrap_err();
+    assert!(match e { ZipError::CrcError(ref name) => *name == "b.txt", _ => false });
+    assert_eq!(format!("{}", e), "CRC mismatch: b.txt");
+
+    let e = zip.info("missing.txt").err().unwrap();
+    assert_eq!(format!("{}", e), "file not found in archive: missing.txt");
+    assert_eq!(format!("{}", zip.by_index(5).err().unwrap()), "file not found in archive");
+}
+
+#[test]
+fn extract_file_matches_read() {
+    let contents: Vec<u8> = (0..200000).map(|i| (i % 251) as u8).collect();
+    let bytes = build_archive(vec![entry("stored", 0, &contents), entry("deflated", 8, &contents),
+                                   entry("empty", 0, b"")]);
+    let mut zip = open_bytes(&bytes);
+    let buffered: Vec<(FileInfo, Vec<u8>)> = zip.entries().map(|e| e.unwrap()).collect();
+    for &(ref f, ref data) in buffered.iter() {
+        let mut streamed = Vec::new();
+        zip.extract_file(f, &mut streamed).unwrap();
+        assert_eq!(&streamed, data);
+    }
+    assert_eq!(buffered[0].1, contents);
+
+    // the crc32 of a stored entry is still checked, after its data was written out
+    let mut corrupt = bytes.clone();
+    corrupt[30 + 6 + 150000] ^= 1;
+    let mut zip = open_bytes(&corrupt);
+    let f = zip.info("stored").unwrap();
+    let mut out = Vec::new();
+    assert!(match zip.extract_file(&f, &mut out) { Err(ZipError::CrcError(..)) => true, _ => false });
+    assert_eq!(out.len(), contents.len());
+}
+
+#[test]
+fn open_entry_seekable() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"before"),
+                                   entry("db", 0, b"0123456789"),
+                                   entry("c.txt", 8, b"compressed")]);
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("db").unwrap();
+    {
+        let mut entry = zip.open_entry_seekable(&f).unwrap();
+        assert_eq!(entry.len(), 10);
+        let mut buf = [0u8; 3];
+        entry.seek(SeekFrom::Start(4)).unwrap();
+        entry.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"456");
+        entry.seek(SeekFrom::End(-2)).unwrap();
+        let mut rest = Vec::new();
+        entry.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"89".to_vec());
+        assert_eq!(entry.seek(SeekFrom::Current(-10)).unwrap(), 0);
+        assert!(entry.seek(SeekFrom::Current(-1)).is_err());
+        entry.seek(SeekFrom::Start(20)).unwrap();
+        assert_eq!(entry.read(&mut buf).unwrap(), 0);
+    }
+    let f = zip.info("c.txt").unwrap();
+    assert!(match zip.open_entry_seekable(&f) {
+        Err(ZipError::UnsupportedCompressionMethod(8)) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn invalid_offset() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 0, b"second")]);
+    let mut zip = open_bytes(&bytes);
+    let directory = zip.end_record().central_directory_offset as u64;
+    let mut out = Vec::new();
+
+    let mut f = zip.info("a.txt").unwrap();
+    for &offset in [directory, directory + 10, bytes.len() as u64 + 100].iter() {
+        f.local_file_header_offset = offset;
+        assert!(match zip.extract_file(&f, &mut out) {
+            Err(ZipError::InvalidOffset(o)) => o == offset,
+            _ => false
+        });
+    }
+
+    // a header in bounds whose data runs into the directory
+    let mut e = entry("b.txt", 0, b"second");
+    e.local.compressed_size = 1000;
+    let bytes = build_archive(vec![entry("a.txt", 0, b"first"), e]);
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("b.txt").unwrap();
+    assert!(match zip.extract_file(&f, &mut out) { Err(ZipError::InvalidOffset(..)) => true, _ => false });
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"first".to_vec());
+}
+
+#[test]
+fn entry_on_other_disk() {
+    let mut e = entry("a.txt", 0, b"elsewhere");
+    e.central.disk_number_start = 2;
+    let bytes = build_archive(vec![e, entry("b.txt", 0, b"here")]);
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("a.txt").unwrap();
+    assert_eq!(f.disk_number_start, 2);
+    let mut out = Vec::new();
+    assert!(match zip.extract_file(&f, &mut out) { Err(ZipError::UnsupportedMultiDisk) => true, _ => false });
+    zip.unzip_one("b.txt", &mut out).unwrap();
+    assert_eq!(out, b"here".to_vec());
+}
+
+#[test]
+fn detect_name_encoding() {
+    let mut utf8 = entry("", 0, b"a");
+    utf8.central.file_name = MaybeUtf8Buf::from_bytes("caf\u{e9}.txt".as_bytes().to_vec());
+    let mut cp437 = entry("", 0, b"b");
+    cp437.central.file_name = MaybeUtf8Buf::from_bytes(vec![b'n', 0x82, 0x9c, b'.', b't', b'x', b't']);
+    let bytes = build_archive(vec![utf8, cp437, entry("plain.txt", 0, b"c")]);
+
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.by_index(1).unwrap().name.as_str(), None);
+
+    let options = ZipReaderOptions::new().detect_name_encoding(true);
+    let mut zip = ZipReader::with_options(Cursor::new(bytes), options).unwrap();
+    let names: Vec<String> = zip.files().map(|f| f.name.as_str().unwrap().to_string()).collect();
+    assert_eq!(names, vec!["caf\u{e9}.txt".to_string(), "n\u{e9}\u{a3}.txt".to_string(),
+                           "plain.txt".to_string()]);
+    let mut out = Vec::new();
+    zip.unzip_one("n\u{e9}\u{a3}.txt", &mut out).unwrap();
+    assert_eq!(out, b"b".to_vec());
+}
+
+#[test]
+fn name_decoder() {
+    let mut flagged = entry("flagged.txt", 0, b"a");
+    flagged.central.general_purpose_bit_flag |= 2048;
+    let mut legacy = entry("", 0, b"b");
+    legacy.central.file_name = MaybeUtf8Buf::from_bytes(vec![b'n', 0xff, b'.', b't', b'x', b't']);
+    let bytes = build_archive(vec![flagged, legacy, entry("plain.txt", 0, b"c")]);
+
+    let mut zip = open_bytes(&bytes);
+    assert_eq!(zip.by_index(1).unwrap().decoded_name(), "n\u{fffd}.txt");
+
+    let options = ZipReaderOptions::new().name_decoder(|name: &[u8]| {
+        String::from_utf8_lossy(name).to_uppercase()
+    });
+    let mut zip = ZipReader::with_options(Cursor::new(bytes), options).unwrap();
+    let names: Vec<String> = zip.files().map(|f| f.decoded_name()).collect();
+    assert_eq!(names, vec!["flagged.txt".to_string(), "N\u{fffd}.TXT".to_string(),
+                           "PLAIN.TXT".to_string()]);
+}
+
+#[test]
+fn data_offset() {
+    let contents = b"deflated deflated deflated";
+    let bytes = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 8, contents)]);
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("b.txt").unwrap();
+    let offset = zip.data_offset(&f).unwrap() as usize;
+    assert_eq!(offset, f.local_file_header_offset as usize + 30 + 5);
+    let data = &bytes[offset..offset + f.compressed_size as usize];
+    assert_eq!(flate::inflate_bytes(data).unwrap().to_vec(), contents.to_vec());
+    assert_eq!(zip.data_offset(&f).unwrap() as usize, offset);
+}
+
+// a Unicode Path extra field for `legacy_name`
+fn unicode_path_extra(legacy_name: &[u8], name: &str) -> Vec<u8> {
+    let mut extra = Vec::new();
+    extra.write_le_u16(0x7075).unwrap();
+    extra.write_le_u16(5 + name.len() as u16).unwrap();
+    extra.push(1);
+    extra.write_le_u32(crc32::crc32(legacy_name)).unwrap();
+    extra.extend(name.bytes());
+    extra
+}
+
+#[test]
+fn unicode_path_extra_field() {
+    let legacy = vec![b'n', 0x82, b'.', b't', b'x', b't'];
+    let extra = unicode_path_extra(&legacy, "n\u{e9}.txt");
+    assert_eq!(format::parse_unicode_path_extra(&extra, &legacy), Some("n\u{e9}.txt".to_string()));
+    // made for a different name
+    assert_eq!(format::parse_unicode_path_extra(&extra, b"renamed.txt"), None);
+    let mut v2 = extra.clone();
+    v2[4] = 2;
+    assert_eq!(format::parse_unicode_path_extra(&v2, &legacy), None);
+
+    let mut e = entry("", 0, b"a");
+    e.central.file_name = MaybeUtf8Buf::from_bytes(legacy.clone());
+    e.central.extra_field = extra;
+    let mut stale = entry("", 0, b"b");
+    stale.central.file_name = MaybeUtf8Buf::from_bytes(b"renamed.txt".to_vec());
+    stale.central.extra_field = unicode_path_extra(&legacy, "original.txt");
+    let bytes = build_archive(vec![e, stale]);
+    let mut zip = open_bytes(&bytes);
+    let names: Vec<String> = zip.files().map(|f| f.decoded_name()).collect();
+    assert_eq!(names, vec!["n\u{e9}.txt".to_string(), "renamed.txt".to_string()]);
+    let mut out = Vec::new();
+    zip.unzip_one("n\u{e9}.txt", &mut out).unwrap();
+    assert_eq!(out, b"a".to_vec());
+}
+
+#[test]
+fn info_name_types() {
+    let bytes = build_archive(vec![entry("dir/a.txt", 0, b"a")]);
+    let mut zip = open_bytes(&bytes);
+    let name = "dir/a.txt";
+    assert_eq!(zip.info(name).unwrap().name, name);
+    assert_eq!(zip.info(name.to_string()).unwrap().name, name);
+    assert_eq!(zip.info(&name.to_string()).unwrap().name, name);
+    assert_eq!(zip.info(name.as_bytes()).unwrap().name, name);
+    assert_eq!(zip.info(name.as_bytes().to_vec()).unwrap().name, name);
+    assert!(zip.contains(b"dir/a.txt".to_vec()));
+    assert!(zip.info_ignore_case(String::from("DIR/A.TXT")).is_ok());
+    match zip.info(String::from("missing")).err().unwrap() {
+        ZipError::FileNotFoundInArchive(n) => assert_eq!(n, "missing"),
+        _ => panic!("expected FileNotFoundInArchive")
+    }
+}
+
+#[test]
+fn raw_files_size_hint() {
+    let bytes = build_archive(vec![entry("a", 0, b"a"), entry("b", 0, b"b"), entry("c", 0, b"c")]);
+    let mut zip = open_bytes(&bytes);
+    let mut files = zip.files_raw();
+    // a broken header could end the iteration anywhere, so only the upper bound is known
+    assert_eq!(files.size_hint(), (0, Some(3)));
+    files.next().unwrap().ok().unwrap();
+    assert_eq!(files.size_hint(), (0, Some(2)));
+    assert_eq!(files.count(), 2);
+    assert_eq!(zip.files_raw().collect::<Vec<_>>().len(), 3);
+}
+
+#[test]
+fn file_iterator_len() {
+    let bytes = build_archive(vec![entry("a", 0, b"a"), entry("b", 0, b"b"), entry("c", 0, b"c")]);
+    let mut zip = open_bytes(&bytes);
+    let mut files = zip.files();
+    assert_eq!(files.len(), 3);
+    files.next();
+    assert_eq!(files.len(), 2);
+    assert_eq!(zip.file_names().count(), 3);
+    assert_eq!(zip.file_names_bytes().unwrap().len(), 3);
+}
+
+#[test]
+fn raw_files_reset() {
+    let bytes = build_archive(vec![entry("a", 0, b"a"), entry("b", 8, b"b")]);
+    let mut zip = open_bytes(&bytes);
+    let mut files = zip.files_raw();
+    let first: Vec<MaybeUtf8Buf> = files.by_ref().map(|f| f.ok().unwrap().name).collect();
+    assert!(files.next().is_none());
+    files.reset();
+    assert_eq!(files.size_hint(), (0, Some(2)));
+    let second: Vec<MaybeUtf8Buf> = files.map(|f| f.ok().unwrap().name).collect();
+    assert_eq!(first, second);
+    assert_eq!(first, vec![MaybeUtf8Buf::from_str("a".to_string()), MaybeUtf8Buf::from_str("b".to_string())]);
+}
+
+#[test]
+fn read_into() {
+    let bytes = build_archive(vec![entry("long.txt", 0, b"the longer entry"), entry("short.txt", 0, b"short")]);
+    let mut zip = open_bytes(&bytes);
+    let infos: Vec<FileInfo> = zip.files().collect();
+    let mut buf = Vec::new();
+    zip.read_into(&infos[0], &mut buf).unwrap();
+    assert_eq!(buf, b"the longer entry".to_vec());
+    zip.read_into(&infos[1], &mut buf).unwrap();
+    assert_eq!(buf, b"short".to_vec());
+
+    // a crafted size is checked against the limit before anything is reserved, and only
+    // part of it is reserved otherwise
+    let mut e = entry("huge.txt", 0, b"short");
+    e.local.uncompressed_size = 0xfffffff0;
+    e.central.uncompressed_size = 0xfffffff0;
+    let bytes = build_archive(vec![e]);
+    let options = ZipReaderOptions::new().max_decompressed_size(1000);
+    let mut zip = ZipReader::with_options(Cursor::new(bytes.clone()), options).unwrap();
+    let f = zip.info("huge.txt").unwrap();
+    let mut buf = Vec::new();
+    assert!(match zip.read_into(&f, &mut buf) {
+        Err(ZipError::DecompressedSizeLimitExceeded) => true,
+        _ => false
+    });
+    assert_eq!(buf.capacity(), 0);
+    open_bytes(&bytes).read_into(&f, &mut buf).unwrap();
+    assert_eq!(buf, b"short".to_vec());
+    assert!(buf.capacity() < 0xfffffff0);
+}
+
+#[test]
+fn skip_crc_verification() {
+    let mut e = entry("a.txt", 0, b"contents");
+    e.local.crc32 ^= 1;
+    e.central.crc32 ^= 1;
+    let bytes = build_archive(vec![e]);
+    let mut out = Vec::new();
+    assert!(match open_bytes(&bytes).unzip_one("a.txt", &mut out) {
+        Err(ZipError::CrcError(..)) => true,
+        _ => false
+    });
+
+    let options = ZipReaderOptions::new().verify_crc(false);
+    let mut zip = ZipReader::with_options(Cursor::new(bytes), options).unwrap();
+    let f = zip.info("a.txt").unwrap();
+    let mut out = Vec::new();
+    zip.extract_file(&f, &mut out).unwrap();
+    assert_eq!(out, b"contents".to_vec());
+    let mut out = Vec::new();
+    zip.extract_first(&f, 100, &mut out).unwrap();
+    assert_eq!(out, b"contents".to_vec());
+    assert!(match zip.verify(&f) { Err(ZipError::CrcError(..)) => true, _ => false });
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn open_mmap() {
+    let dir = TempDir::new("zip-mmap").unwrap();
+    let path = dir.path().join("a.zip");
+    let bytes = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 0, b"second")]);
+    File::create(&path).unwrap().write_all(&bytes).unwrap();
+    let mut zip = ZipReader::open_mmap(&path).unwrap();
+    let mut out = Vec::new();
+    zip.unzip_one("b.txt", &mut out).unwrap();
+    assert_eq!(out, b"second".to_vec());
+}
+
+#[test]
+fn extract_parallel() {
+    let mut entries = vec![entry("dir/", 0, b"")];
+    for i in 0..10 {
+        let contents = format!("contents of entry {}", i);
+        entries.push(entry(&format!("dir/{}.txt", i), 0, contents.as_bytes()));
+    }
+    let bytes = build_archive(entries);
+    let sequential = TempDir::new("zip-sequential").unwrap();
+    open_bytes(&bytes).extract_to(sequential.path(), || false).unwrap();
+    let parallel = TempDir::new("zip-parallel").unwrap();
+    open_bytes(&bytes).extract_parallel(parallel.path(), 3).unwrap();
+    for i in 0..10 {
+        let name = format!("dir/{}.txt", i);
+        let mut a = Vec::new();
+        File::open(sequential.path().join(&name)).unwrap().read_to_end(&mut a).unwrap();
+        let mut b = Vec::new();
+        File::open(parallel.path().join(&name)).unwrap().read_to_end(&mut b).unwrap();
+        assert_eq!(a, b);
+    }
+}
+
+#[test]
+fn content_fingerprint() {
+    let stored = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 0, b"second")]);
+    let deflated = build_archive(vec![entry("b.txt", 8, b"second"), entry("a.txt", 8, b"first")]);
+    let changed = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 0, b"Second")]);
+    let fingerprint = open_bytes(&stored).content_fingerprint().unwrap();
+    assert_eq!(open_bytes(&deflated).content_fingerprint().unwrap(), fingerprint);
+    assert!(open_bytes(&changed).content_fingerprint().unwrap() != fingerprint);
+}
+
+#[test]
+fn host_os() {
+    let mut unix = entry("unix.txt", 0, b"a");
+    unix.central.version_made_by = (3 << 8) | 20;
+    let mut mac = entry("mac.txt", 0, b"b");
+    mac.central.version_made_by = (19 << 8) | 20;
+    let mut odd = entry("odd.txt", 0, b"c");
+    odd.central.version_made_by = 200 << 8;
+    let bytes = build_archive(vec![unix, mac, odd, entry("dos.txt", 0, b"d")]);
+    let hosts: Vec<HostOs> = open_bytes(&bytes).files().map(|f| f.host_os()).collect();
+    assert_eq!(hosts, vec![HostOs::Unix, HostOs::OsX, HostOs::Unknown(200), HostOs::MsDos]);
+}
+
+#[test]
+fn data_descriptor_round_trip() {
+    for &(signature_present, zip64) in [(true, false), (false, false), (true, true), (false, true)].iter() {
+        let dd = format::DataDescriptor {
+            signature_present: signature_present,
+            crc32: 0xdeadbeef,
+            compressed_size: if zip64 { 0x100000000 } else { 5 },
+            uncompressed_size: 7,
+        };
+        let mut bytes = Vec::new();
+        dd.write(&mut bytes, zip64).unwrap();
+        let expected_len = if signature_present { 4 } else { 0 } + if zip64 { 20 } else { 12 };
+        assert_eq!(bytes.len(), expected_len);
+        let read = format::DataDescriptor::read(&mut Cursor::new(bytes), zip64).unwrap();
+        assert_eq!(read.signature_present, signature_present);
+        assert_eq!(read.crc32, dd.crc32);
+        assert_eq!((read.compressed_size, read.uncompressed_size), (dd.compressed_size, dd.uncompressed_size));
+    }
+
+    let big = format::DataDescriptor { signature_present: true, crc32: 0, compressed_size: 0x100000000,
+                                       uncompressed_size: 0 };
+    assert!(match big.write(&mut Vec::new(), false) { Err(ZipError::TooLongField) => true, _ => false });
+}
+
+#[test]
+fn is_zip64() {
+    let classic = build_archive(vec![entry("a.txt", 0, b"a")]);
+    assert!(!open_bytes(&classic).is_zip64());
+
+    // insert a Zip64 end of central directory record and locator before the end record
+    let end = classic.len() - 22;
+    let directory_offset = open_bytes(&classic).end_record().central_directory_offset;
+    let directory_size = end as u32 - directory_offset;
+    let mut zip64 = classic[..end].to_vec();
+    zip64.write_le_u32(0x06064b50).unwrap();
+    zip64.write_le_u64(44).unwrap();
+    zip64.write_le_u16(45).unwrap();
+    zip64.write_le_u16(45).unwrap();
+    zip64.write_le_u32(0).unwrap();
+    zip64.write_le_u32(0).unwrap();
+    zip64.write_le_u64(1).unwrap();
+    zip64.write_le_u64(1).unwrap();
+    zip64.write_le_u64(directory_size as u64).unwrap();
+    zip64.write_le_u64(directory_offset as u64).unwrap();
+    zip64.write_le_u32(0x07064b50).unwrap();
+    zip64.write_le_u32(0).unwrap();
+    zip64.write_le_u64(end as u64).unwrap();
+    zip64.write_le_u32(1).unwrap();
+    zip64.extend_from_slice(&classic[end..]);
+    let mut zip = open_bytes(&zip64);
+    assert!(zip.is_zip64());
+    let mut out = Vec::new();
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"a".to_vec());
+
+    // an end record leaving the entry counts to the Zip64 record
+    let mut sentinel = classic.clone();
+    sentinel[end + 8..end + 12].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    assert!(open_bytes(&sentinel).is_zip64());
+}
+
+#[test]
+fn file_info_display() {
+    let mut e = entry("dir/a.txt", 0, b"some contents");
+    e.central.last_modified_datetime = format::MsdosDateTime::new(2015, 6, 1, 12, 30, 4);
+    let bytes = build_archive(vec![e]);
+    let f = open_bytes(&bytes).info("dir/a.txt").unwrap();
+    assert_eq!(format!("{}", f), "dir/a.txt (Store, 13 -> 13 bytes, 2015-06-01 12:30:04)");
+    assert!(format!("{:?}", f).starts_with("FileInfo {"));
+}
+
+// a local Zip64 extra field with both sizes
+fn zip64_extra(uncompressed: u64, compressed: u64) -> Vec<u8> {
+    let mut extra = Vec::new();
+    extra.write_le_u16(0x0001).unwrap();
+    extra.write_le_u16(16).unwrap();
+    extra.write_le_u64(uncompressed).unwrap();
+    extra.write_le_u64(compressed).unwrap();
+    extra
+}
+
+#[test]
+fn local_zip64_sizes() {
+    let mut e = entry("a.txt", 0, b"contents");
+    e.local.compressed_size = 0xffffffff;
+    e.local.uncompressed_size = 0xffffffff;
+    e.local.extra_field = zip64_extra(8, 8);
+    let bytes = build_archive(vec![e]);
+    let mut zip = ZipReader::with_options(Cursor::new(bytes), ZipReaderOptions::new().strict(true)).unwrap();
+    let mut out = Vec::new();
+    zip.unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"contents".to_vec());
+
+    let mut e = entry("big.txt", 0, b"contents");
+    e.local.compressed_size = 0xffffffff;
+    e.local.uncompressed_size = 0xffffffff;
+    e.local.extra_field = zip64_extra(0x100000000, 0x100000000);
+    let bytes = build_archive(vec![e]);
+    // the sizes are taken at their word, which puts the end of the data past the archive
+    assert!(match open_bytes(&bytes).unzip_one("big.txt", &mut Vec::new()) {
+        Err(ZipError::InvalidOffset(0)) => true,
+        _ => false
+    });
+}
+
+#[test]
+fn central_zip64_fields() {
+    // the sizes and offset of b.txt are left to the Zip64 extra field of its central header
+    let mut e = entry("b.txt", 0, b"contents");
+    e.central.compressed_size = 0xffffffff;
+    e.central.uncompressed_size = 0xffffffff;
+    e.central.extra_field = format::zip64_extra_field(&[8, 8, 40]);
+    let mut bytes = build_archive(vec![entry("a.txt", 0, b"first"), e]);
+    let directory = open_bytes(&bytes).end_record().central_directory_offset as usize;
+    let offset_field = directory + 46 + 5 + 42;
+    bytes[offset_field..offset_field + 4].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("b.txt").unwrap();
+    assert_eq!((f.uncompressed_size, f.compressed_size, f.local_file_header_offset), (8, 8, 40));
+    let mut out = Vec::new();
+    zip.extract_file(&f, &mut out).unwrap();
+    assert_eq!(out, b"contents".to_vec());
+
+    // sizes past 4 GB come through whole
+    let mut e = entry("big.txt", 0, b"contents");
+    e.central.uncompressed_size = 0xffffffff;
+    e.central.extra_field = format::zip64_extra_field(&[0x100000000]);
+    let bytes = build_archive(vec![e]);
+    let f = open_bytes(&bytes).info("big.txt").unwrap();
+    assert_eq!((f.uncompressed_size, f.compressed_size), (0x100000000, 8));
+}
+
+#[test]
+fn file_entries() {
+    let bytes = build_archive(vec![entry("dir/", 0, b""), entry("dir/a.txt", 0, b"a"),
+                                   entry("win\\", 0, b""), entry("empty.txt", 0, b""),
+                                   entry("dir/sub/", 0, b""), entry("b.txt", 0, b"b")]);
+    let mut zip = open_bytes(&bytes);
+    let names: Vec<MaybeUtf8Buf> = zip.file_entries().map(|f| f.name).collect();
+    assert_eq!(names, vec![MaybeUtf8Buf::from_str("dir/a.txt".to_string()),
+                           MaybeUtf8Buf::from_str("empty.txt".to_string()),
+                           MaybeUtf8Buf::from_str("b.txt".to_string())]);
+    assert_eq!(zip.files().count(), 6);
+}
+
+#[test]
+fn maybe_utf8_affixes() {
+    let utf8 = MaybeUtf8Buf::from_str("docs/café/".to_string());
+    let bytes = MaybeUtf8Buf::from_bytes(b"docs/caf\xe9/".to_vec());
+    for name in [&utf8, &bytes].iter() {
+        assert!(name.starts_with(b"docs/"));
+        assert!(name.starts_with(b""));
+        assert!(!name.starts_with(b"doc/"));
+        assert!(name.ends_with(b"/"));
+        assert!(name.ends_with_char('/'));
+        assert!(!name.ends_with_char('\\'));
+        assert!(name.to_slice().starts_with(b"docs/caf"));
+        assert!(name.to_slice().ends_with_char('/'));
+    }
+    assert!(utf8.ends_with("é/".as_bytes()));
+    assert!(!bytes.ends_with("é/".as_bytes()));
+    assert!(MaybeUtf8Slice::from_str("café").ends_with_char('é'));
+    assert!(!MaybeUtf8Slice::from_bytes(b"caf\xe9").ends_with_char('é'));
+    assert!(!MaybeUtf8Buf::new().ends_with_char('/'));
+}
+
+#[test]
+fn maybe_utf8_split_bytes() {
+    let utf8 = MaybeUtf8Buf::from_str("a/b/c/".to_string());
+    let segments: Vec<&[u8]> = utf8.split_bytes(b'/').collect();
+    assert_eq!(segments, vec![&b"a"[..], &b"b"[..], &b"c"[..], &b""[..]]);
+
+    let bytes = MaybeUtf8Buf::from_bytes(b"caf\xe9//x".to_vec());
+    let segments: Vec<&[u8]> = bytes.split_bytes(b'/').collect();
+    assert_eq!(segments, vec![&b"caf\xe9"[..], &b""[..], &b"x"[..]]);
+    assert_eq!(MaybeUtf8Slice::from_str("dir\\file").split_bytes(b'\\').count(), 2);
+
+    // as with `[u8]::split`, an empty name is one empty segment
+    let empty = MaybeUtf8Buf::new();
+    let segments: Vec<&[u8]> = empty.split_bytes(b'/').collect();
+    assert_eq!(segments, vec![&b""[..]]);
+}
+
+#[test]
+fn try_file_names() {
+    let mut bytes = build_archive(vec![entry("a.txt", 0, b"a"), entry("b.txt", 0, b"b"),
+                                       entry("c.txt", 0, b"c")]);
+    let second = open_bytes(&bytes).end_record().central_directory_offset as usize + 46 + 5;
+    bytes[second] = b'X';
+    let mut zip = open_bytes(&bytes);
+    let names: Vec<Result<MaybeUtf8Buf, ZipError>> = zip.try_file_names().collect();
+    assert_eq!(names.len(), 2);
+    assert_eq!(*names[0].as_ref().unwrap(), "a.txt");
+    assert!(match names[1] { Err(ZipError::InvalidSignature(..)) => true, _ => false });
+
+    // the listings that don't yield errors stop at the broken header instead of panicking
+    let names: Vec<MaybeUtf8Buf> = zip.file_names().collect();
+    assert_eq!(names, vec![MaybeUtf8Buf::from_str("a.txt".to_string())]);
+    assert!(match zip.files_with_prefix("") { Err(ZipError::InvalidSignature(..)) => true, _ => false });
+    assert!(match zip.files_by_offset() { Err(ZipError::InvalidSignature(..)) => true, _ => false });
+}
+
+#[test]
+fn write_zip64_entry_count() {
+    let mut w = ZipWriter::new(Cursor::new(Vec::new()));
+    for i in 0..0xffff {
+        w.start_file(&format!("{}", i), CompressionMethod::Store).unwrap();
+    }
+    let bytes = w.finish().unwrap().into_inner();
+    let end = bytes.len() - 22;
+    assert_eq!(&bytes[end + 8..end + 12], &[0xff, 0xff, 0xff, 0xff]);
+    let locator = format::Zip64EndOfCentralDirectoryLocator::read(&mut &bytes[end - 20..end]).unwrap();
+    let record = format::Zip64EndOfCentralDirectoryRecord::read(
+        &mut &bytes[locator.zip64_end_record_offset as usize..]).unwrap();
+    assert_eq!(record.total_entry_count, 0xffff);
+
+    let mut zip = open_bytes(&bytes);
+    assert!(zip.is_zip64());
+    assert_eq!(zip.files().count(), 0xffff);
+    assert!(zip.contains("65534"));
+}
+
+// keeps only the start and the end of what is written, for archives too large to hold
+struct HeadAndTail {
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    len: u64,
+}
+
+impl Write for HeadAndTail {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = 4096 - self.head.len();
+        self.head.extend_from_slice(&buf[..cmp::min(buf.len(), room)]);
+        self.tail.extend_from_slice(buf);
+        if self.tail.len() > 1 << 20 {
+            let drop = self.tail.len() - 4096;
+            self.tail.drain(..drop);
+        }
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+#[test]
+fn read_back_zip64() {
+    let mut w = ZipWriter::new_in_memory();
+    let options = FileOptions::new().compression_method(CompressionMethod::Store).large_file(true);
+    w.start_file_with_options("a.txt", options).unwrap();
+    w.write_all(b"forced into Zip64").unwrap();
+    w.start_file("b.txt", CompressionMethod::Store).unwrap();
+    w.write_all(b"classic").unwrap();
+    let mut bytes = w.finish_into_bytes().unwrap();
+
+    let check = |bytes: &[u8]| {
+        let mut zip = open_bytes(bytes);
+        assert!(zip.is_zip64());
+        assert_eq!(zip.entry_count(), 2);
+        let f = zip.info("a.txt").unwrap();
+        assert_eq!((f.requires_version(), f.uncompressed_size), (45, 17));
+        let mut out = Vec::new();
+        zip.extract_file(&f, &mut out).unwrap();
+        assert_eq!(out, b"forced into Zip64".to_vec());
+        out.clear();
+        zip.unzip_one("b.txt", &mut out).unwrap();
+        assert_eq!(out, b"classic".to_vec());
+    };
+    check(&bytes);
+    // leave the entry count and the directory's size and offset to the Zip64 end record
+    let end = bytes.len() - 22;
+    for b in bytes[end + 8..end + 20].iter_mut() {
+        *b = 0xff;
+    }
+    check(&bytes);
+}
+
+// slow, as it writes over 4 GB
+#[test]
+#[ignore]
+fn write_zip64_entry() {
+    let size = 0x100000000u64 + 10;
+    let mut w = ZipWriter::new(HeadAndTail { head: Vec::new(), tail: Vec::new(), len: 0 });
+    let options = FileOptions::new().compression_method(CompressionMethod::Store).large_file(true)
+                                    .data_descriptor(true);
+    w.start_file_with_options("big", options).unwrap();
+    let chunk = vec![0u8; 1 << 20];
+    let mut written = 0u64;
+    while written < size {
+        let n = cmp::min(chunk.len() as u64, size - written) as usize;
+        w.write_all(&chunk[..n]).unwrap();
+        written += n as u64;
+    }
+    let out = w.finish().unwrap();
+
+    // the sizes follow the data
+    let local = format::LocalFileHeader::read(&mut &out.head[..]).unwrap();
+    assert!(local.has_data_descriptor());
+    assert_eq!((local.compressed_size, local.uncompressed_size), (0xffffffff, 0xffffffff));
+    assert_eq!(local.zip64_sizes(), Some((0, 0)));
+
+    let tail = &out.tail[out.tail.len() - 4096..];
+    let end = tail.len() - 22;
+    let end_record = format::EndOfCentralDirectoryRecord::read(&mut &tail[end..]).unwrap();
+    assert_eq!(end_record.central_directory_offset, 0xffffffff);
+    format::Zip64EndOfCentralDirectoryLocator::read(&mut &tail[end - 20..end]).unwrap();
+    let record_start = end - 20 - 56;
+    let record = format::Zip64EndOfCentralDirectoryRecord::read(&mut &tail[record_start..end - 20]).unwrap();
+    assert_eq!(record.total_entry_count, 1);
+    assert_eq!(record.central_directory_offset, 30 + 3 + 20 + size + 24);
+    let directory_start = record_start - record.central_directory_size as usize;
+    let dd = format::DataDescriptor::read(&mut &tail[directory_start - 24..], true).unwrap();
+    assert_eq!((dd.compressed_size, dd.uncompressed_size), (size, size));
+    let central = format::CentralDirectoryHeader::read(&mut &tail[directory_start..]).unwrap();
+    assert_eq!(central.compressed_size, 0xffffffff);
+    assert_eq!(central.version_needed_to_extract, 45);
+    let mut extra = format::find_extra_field(&central.extra_field, format::ZIP64_EXTRA_FIELD_ID).unwrap();
+    assert_eq!(extra.len(), 16);
+    assert_eq!((extra.read_le_u64().unwrap(), extra.read_le_u64().unwrap()), (size, size));
+}
+
+#[test]
+fn header_total_size() {
+    let mut central = format::CentralDirectoryHeader::new();
+    central.file_name = MaybeUtf8Buf::from_bytes(vec![b'a'; 0xffff]);
+    central.extra_field = vec![0; 0xffff];
+    central.file_comment = MaybeUtf8Buf::from_bytes(vec![b'c'; 0xffff]);
+    assert_eq!(central.total_size(), 46 + 3 * 0xffff);
+    let mut bytes = Vec::new();
+    central.write(&mut bytes).unwrap();
+    assert_eq!(bytes.len() as u64, central.total_size());
+
+    let mut local = format::LocalFileHeader::new();
+    local.file_name = MaybeUtf8Buf::from_bytes(vec![b'a'; 0xffff]);
+    local.extra_field = vec![0; 0xffff];
+    assert_eq!(local.total_size(), 30 + 2 * 0xffff);
+}
+
+#[test]
+fn extract_by_name() {
+    let bytes = build_archive(vec![entry("a.txt", 0, b"first"), entry("b.txt", 8, b"second")]);
+    let mut zip = open_bytes(&bytes);
+    let mut out = Vec::new();
+    zip.extract_by_name("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"first".to_vec());
+    match zip.extract_by_name("c.txt", &mut Vec::new()) {
+        Err(ZipError::FileNotFoundInArchive(name)) => assert_eq!(name, "c.txt"),
+        _ => panic!("expected FileNotFoundInArchive")
+    }
+}
+
+#[test]
+fn extract_file_with_progress() {
+    let contents: Vec<u8> = (0..150000).map(|i| i as u8).collect();
+    let bytes = build_archive(vec![entry("big.bin", 0, &contents[..]), entry("empty", 0, b"")]);
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("big.bin").unwrap();
+    let mut calls = Vec::new();
+    let mut out = Vec::new();
+    zip.extract_file_with_progress(&f, &mut out, &mut |done, total| calls.push((done, total))).unwrap();
+    assert_eq!(out, contents);
+    assert_eq!(calls, vec![(65536, 150000), (131072, 150000), (150000, 150000)]);
+
+    let f = zip.info("empty").unwrap();
+    let mut calls = Vec::new();
+    zip.extract_file_with_progress(&f, &mut Vec::new(), &mut |done, total| calls.push((done, total))).unwrap();
+    assert_eq!(calls, vec![(0, 0)]);
+
+    // Deflate entries are written on, and reported, as they are inflated
+    let contents: Vec<u8> = (0..300000).map(|i| (i % 251) as u8).collect();
+    let bytes = build_archive(vec![entry("big.txt", 8, &contents[..]), entry("empty.txt", 8, b"")]);
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("big.txt").unwrap();
+    let mut calls = Vec::new();
+    let mut out = Vec::new();
+    zip.extract_file_with_progress(&f, &mut out, &mut |done, total| calls.push((done, total))).unwrap();
+    assert_eq!(out, contents);
+    assert!(calls.len() > 1);
+    assert!(calls.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(calls.last(), Some(&(300000, 300000)));
+
+    let f = zip.info("empty.txt").unwrap();
+    let mut calls = Vec::new();
+    zip.extract_file_with_progress(&f, &mut Vec::new(), &mut |done, total| calls.push((done, total))).unwrap();
+    assert_eq!(calls, vec![(0, 0)]);
+}
+
+#[test]
+fn stored_size_mismatch() {
+    let mut e = entry("a.txt", 0, b"contents");
+    e.local.uncompressed_size = 100;
+    e.central.uncompressed_size = 100;
+    let bytes = build_archive(vec![e]);
+    let mut out = Vec::new();
+    open_bytes(&bytes).unzip_one("a.txt", &mut out).unwrap();
+    assert_eq!(out, b"contents".to_vec());
+
+    let mut zip = open_bytes(&bytes);
+    zip.set_strict(true);
+    let f = zip.info("a.txt").unwrap();
+    assert!(match zip.extract_file(&f, &mut Vec::new()) { Err(ZipError::HeaderMismatch) => true, _ => false });
+}
+
+#[test]
+fn verify_crc_of_buffer() {
+    let bytes = build_archive(vec![entry("a.txt", 8, b"contents")]);
+    let f = open_bytes(&bytes).info("a.txt").unwrap();
+    fileinfo::verify_crc(&f, b"contents").unwrap();
+    match fileinfo::verify_crc(&f, b"Contents") {
+        Err(ZipError::CrcError(name)) => assert_eq!(name, "a.txt"),
+        _ => panic!("expected CrcError")
+    }
+}
+
+#[test]
+fn treat_unknown_as_store() {
+    let mut e = entry("a.bin", 0, b"raw data");
+    e.local.compression_method = 99;
+    e.central.compression_method = 99;
+    let bytes = build_archive(vec![e]);
+    let mut zip = open_bytes(&bytes);
+    let f = zip.info("a.bin").unwrap();
+    assert_eq!(f.compression_method, CompressionMethod::Unknown(99));
+    assert!(zip.extract_file(&f, &mut Vec::new()).is_err());
+
+    let options = ZipReaderOptions::new().treat_unknown_as_store(true);
+    let mut zip = ZipReader::with_options(Cursor::new(bytes), options).unwrap();
+    let mut out = Vec::new();
+    zip.extract_file(&f, &mut out).unwrap();
+    assert_eq!(out, b"raw data".to_vec());
+}
@@ -0,0 +1,47 @@
+//! Little-endian integer helpers, which `std::io` leaves out.
+
+use std::io;
+use std::io::{Read, Write};
+
+pub trait ReadExt: Read {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut b = [0u8; 1];
+        try!(self.read_exact(&mut b));
+        Ok(b[0])
+    }
+
+    fn read_le_u16(&mut self) -> io::Result<u16> {
+        let mut b = [0u8; 2];
+        try!(self.read_exact(&mut b));
+        Ok(u16::from_le_bytes(b))
+    }
+
+    fn read_le_u32(&mut self) -> io::Result<u32> {
+        let mut b = [0u8; 4];
+        try!(self.read_exact(&mut b));
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn read_le_u64(&mut self) -> io::Result<u64> {
+        let mut b = [0u8; 8];
+        try!(self.read_exact(&mut b));
+        Ok(u64::from_le_bytes(b))
+    }
+
+    // reads exactly `len` bytes into a new vector
+    fn read_vec(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut v = vec![0u8; len];
+        try!(self.read_exact(&mut v));
+        Ok(v)
+    }
+}
+
+impl<R: Read + ?Sized> ReadExt for R {}
+
+pub trait WriteExt: Write {
+    fn write_le_u16(&mut self, x: u16) -> io::Result<()> { self.write_all(&x.to_le_bytes()) }
+    fn write_le_u32(&mut self, x: u32) -> io::Result<()> { self.write_all(&x.to_le_bytes()) }
+    fn write_le_u64(&mut self, x: u64) -> io::Result<()> { self.write_all(&x.to_le_bytes()) }
+}
+
+impl<W: Write + ?Sized> WriteExt for W {}
@@ -0,0 +1,513 @@
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::io::{Cursor, Read, Write, Seek, SeekFrom};
+use std::path::Path;
+use error::ZipError;
+use util::WriteExt;
+use maybe_utf8::{MaybeUtf8Slice, IntoMaybeUtf8};
+use crc32;
+use flate;
+use format;
+use fileinfo::{CompressionMethod, FileInfo};
+use reader::ZipReader;
+
+// the upper byte of version_made_by is the host system, which tells how to read
+// external_file_attributes; the lower byte is the spec version we implement (2.0)
+#[cfg(unix)]
+static DEFAULT_VERSION_MADE_BY: u16 = (3 << 8) | 20;
+#[cfg(not(unix))]
+static DEFAULT_VERSION_MADE_BY: u16 = 20;
+
+// the lower half of the external attributes holds the MS-DOS ones, whichever the host
+static MSDOS_DIRECTORY_ATTRIBUTE: u32 = 0x10;
+
+// Zip64 needs version 4.5 of the specification to extract
+static ZIP64_VERSION: u16 = 45;
+
+pub struct ZipWriter<W> {
+    writer: W,
+    offset: u64,
+    files: Vec<format::CentralDirectoryHeader>,
+    current: Option<PendingFile>,
+    version_made_by: u16,
+    comment: Vec<u8>,
+}
+
+/// Settings for an entry started with `ZipWriter::start_file_with_options`, built up from
+/// the defaults of `start_file`, e.g. `FileOptions::new().unix_mode(0o755)`.
+#[derive(Clone)]
+pub struct FileOptions {
+    compression_method: CompressionMethod,
+    unix_mode: Option<u32>,
+    last_modified: Option<Timestamp>,
+    large_file: bool,
+    data_descriptor: bool,
+}
+
+// a modification time as given, checked when the entry is started
+#[derive(Clone)]
+enum Timestamp {
+    DateTime(usize, usize, usize, usize, usize, usize),
+    UnixTime(i64),
+}
+
+impl FileOptions {
+    pub fn new() -> FileOptions {
+        FileOptions { compression_method: CompressionMethod::Deflate, unix_mode: None,
+                      last_modified: None, large_file: false, data_descriptor: false }
+    }
+
+    /// Deflate by default.
+    pub fn compression_method(mut self, method: CompressionMethod) -> FileOptions {
+        self.compression_method = method;
+        self
+    }
+
+    /// Sets the Unix mode of the entry (e.g. 0o755 for a script), which goes to the upper
+    /// half of the external attributes. The entry then says it was made on Unix whatever
+    /// `version_made_by` is, since only extractors on Unix hosts look at the mode.
+    pub fn unix_mode(mut self, mode: u32) -> FileOptions {
+        self.unix_mode = Some(mode);
+        self
+    }
+
+    /// Sets the modification time of the entry, which is otherwise all zeros.
+    /// `start_file_with_options` fails with `InvalidDateTime` if `MsdosDateTime::try_new`
+    /// would.
+    pub fn last_modified_datetime(mut self, year: usize, month: usize, day: usize,
+                                  hour: usize, minute: usize, second: usize) -> FileOptions {
+        self.last_modified = Some(Timestamp::DateTime(year, month, day, hour, minute, second));
+        self
+    }
+
+    /// As `last_modified_datetime`, from seconds since the Unix epoch; see
+    /// `MsdosDateTime::from_unix_time`.
+    pub fn last_modified_unix_time(mut self, time: i64) -> FileOptions {
+        self.last_modified = Some(Timestamp::UnixTime(time));
+        self
+    }
+
+    /// Writes the sizes of the entry to Zip64 extra fields even if they fit in the headers,
+    /// which then needs an extractor supporting Zip64. Entries over 4 GB get them anyway,
+    /// except those written with `data_descriptor`, which go out before their size is known:
+    /// those that may grow past 4 GB have to be started with this set, and writing more fails
+    /// with `EntryTooLarge` otherwise.
+    pub fn large_file(mut self, large: bool) -> FileOptions {
+        self.large_file = large;
+        self
+    }
+
+    /// Entries are buffered until they are finished, so that the local header can give their
+    /// crc32 and sizes. With this set, a stored entry is passed straight on to the writer
+    /// instead, for data too large to hold in memory whose size isn't known up front: the
+    /// local header leaves the crc32 and sizes to a data descriptor after the data, which
+    /// some streaming readers can't find. Compressed entries are buffered whatever this says.
+    pub fn data_descriptor(mut self, data_descriptor: bool) -> FileOptions {
+        self.data_descriptor = data_descriptor;
+        self
+    }
+}
+
+// the entry being written; its data is buffered until the crc32 and sizes are known, except
+// for a stored entry written with a data descriptor, which goes straight on to the writer.
+// The crc32 and size are counted as the data comes either way.
+struct PendingFile {
+    header: format::CentralDirectoryHeader,
+    // where the local header of an entry with a data descriptor was written
+    streamed_from: Option<u64>,
+    data: Vec<u8>,
+    crc32: u32,
+    size: u64,
+    large_file: bool,
+}
+
+impl ZipWriter<File> {
+    pub fn create(path: &Path) -> Result<ZipWriter<File>, ZipError> {
+        Ok(ZipWriter::new(try!(File::create(path))))
+    }
+}
+
+impl ZipWriter<Cursor<Vec<u8>>> {
+    /// Builds an archive in memory; see `finish_into_bytes`.
+    pub fn new_in_memory() -> ZipWriter<Cursor<Vec<u8>>> {
+        ZipWriter::new(Cursor::new(Vec::new()))
+    }
+
+    /// Finishes the archive like `finish`, returning its bytes.
+    pub fn finish_into_bytes(self) -> Result<Vec<u8>, ZipError> {
+        Ok(try!(self.finish()).into_inner())
+    }
+}
+
+impl<W:Write> ZipWriter<W> {
+    pub fn new(writer: W) -> ZipWriter<W> {
+        ZipWriter {
+            writer: writer,
+            offset: 0,
+            files: Vec::new(),
+            current: None,
+            version_made_by: DEFAULT_VERSION_MADE_BY,
+            comment: Vec::new(),
+        }
+    }
+
+    /// Overrides the `version_made_by` written for every entry, which otherwise records the
+    /// host system the archive is written on. Fixing it (e.g. to 20, an MS-DOS host) makes
+    /// archives built from the same inputs byte-identical across platforms, at the cost of
+    /// Unix permissions in the external attributes no longer being meaningful to extractors.
+    pub fn set_version_made_by(&mut self, version: u16) {
+        self.version_made_by = version;
+    }
+
+    /// Starts a new entry, finishing the previous one.
+    /// Anything written to the `ZipWriter` afterwards becomes the contents of the entry.
+    pub fn start_file<'a, T>(&mut self, name: T, method: CompressionMethod) -> Result<(), ZipError>
+            where T: IntoMaybeUtf8<MaybeUtf8Slice<'a>> {
+        self.start_file_with_options(name, FileOptions::new().compression_method(method))
+    }
+
+    /// As `start_file`, with the settings in `options`. Only Store and Deflate can be written;
+    /// other compression methods fail with `UnsupportedCompressionMethod` before anything is
+    /// written, leaving the previous entry open.
+    pub fn start_file_with_options<'a, T>(&mut self, name: T, options: FileOptions) -> Result<(), ZipError>
+            where T: IntoMaybeUtf8<MaybeUtf8Slice<'a>> {
+        match options.compression_method {
+            CompressionMethod::Store | CompressionMethod::Deflate => {}
+            method => return Err(ZipError::UnsupportedCompressionMethod(method.to_u16()))
+        }
+        try!(self.finish_file());
+        let mut h = try!(entry_header(name.into_maybe_utf8(), &options));
+        let streamed_from = if options.data_descriptor && options.compression_method == CompressionMethod::Store {
+            Some(try!(self.write_streamed_header(&mut h, options.large_file)))
+        } else {
+            None
+        };
+        self.current = Some(PendingFile { header: h, streamed_from: streamed_from, data: Vec::new(),
+                                          crc32: 0, size: 0, large_file: options.large_file });
+        Ok(())
+    }
+
+    // writes the local header of a stored entry ahead of its data, returning its offset
+    fn write_streamed_header(&mut self, h: &mut format::CentralDirectoryHeader,
+                             large_file: bool) -> Result<u64, ZipError> {
+        h.general_purpose_bit_flag |= 8;
+        h.version_needed_to_extract = if large_file { ZIP64_VERSION } else { 10 };
+        let mut local = local_file_header(h);
+        if large_file {
+            // the extra field tells that the data descriptor has 8-byte sizes
+            local.compressed_size = 0xffffffff;
+            local.uncompressed_size = 0xffffffff;
+            local.extra_field = format::zip64_extra_field(&[0, 0]);
+        }
+        let offset = self.offset;
+        try!(local.write(&mut self.writer));
+        self.offset += local.total_size();
+        Ok(offset)
+    }
+
+    /// Adds the contents of the file at `path` as a new entry,
+    /// copying it over without the caller having to read it first. A stored file is read
+    /// twice, once for its crc32 and size and then to copy it a chunk at a time, so that it
+    /// is written with a complete local header without being held in memory. Compressed
+    /// files are read whole before being compressed.
+    pub fn add_file_from_path<'a, T>(&mut self, name: T, path: &Path,
+                                     method: CompressionMethod) -> Result<(), ZipError>
+            where T: IntoMaybeUtf8<MaybeUtf8Slice<'a>> {
+        let mut file = try!(File::open(path));
+        if method != CompressionMethod::Store {
+            try!(self.start_file(name, method));
+            try!(io::copy(&mut file, self));
+            return Ok(());
+        }
+        try!(self.finish_file());
+        let options = FileOptions::new().compression_method(method);
+        let mut h = try!(entry_header(name.into_maybe_utf8(), &options));
+        let (crc32, size) = try!(copy_counting(&mut file, &mut io::sink()));
+        try!(file.seek(SeekFrom::Start(0)));
+
+        h.crc32 = crc32;
+        h.version_needed_to_extract = 10;
+        h.version_made_by = self.made_by(&h);
+        let offset = self.offset;
+        let zip64_sizes = set_central_fields(&mut h, size, size, offset, false);
+        try!(self.write_local_header(&h, size, size, zip64_sizes));
+        let (copied_crc32, copied) = try!(copy_counting(&mut (&mut file).take(size), &mut self.writer));
+        self.offset += copied;
+        if (copied_crc32, copied) != (crc32, size) {
+            return Err(ZipError::IoError(io::Error::new(io::ErrorKind::Other,
+                                                        "file changed while it was added")));
+        }
+        self.files.push(h);
+        Ok(())
+    }
+
+    /// Copies an entry from `reader` as it is stored, without decompressing and compressing
+    /// it again. Everything in its central directory header is kept: the name bytes, the
+    /// compression method, crc32, sizes and modification time, the attributes, the comment
+    /// and extra fields, and the versions made by and needed to extract.
+    /// Encrypted entries can't be copied yet, as the local extra fields aren't carried over.
+    pub fn copy_from<R:Read+Seek>(&mut self, reader: &mut ZipReader<R>, f: &FileInfo) -> Result<(), ZipError> {
+        if f.is_encrypted {
+            return Err(ZipError::Encrypted);
+        }
+        try!(self.finish_file());
+        let data = try!(reader.read_raw(f));
+        let mut h = try!(reader.central_directory_header(f));
+        // the data follows the header here, and the compression options and UTF-8 flag
+        // are all that is left of the flags
+        h.general_purpose_bit_flag &= 2 | 4 | 2048;
+        // the Zip64 field is written anew for where the entry ends up
+        let extra = h.extra_field.clone();
+        h.extra_field = Vec::new();
+        for (id, field) in format::extra_fields(&extra) {
+            if id != format::ZIP64_EXTRA_FIELD_ID {
+                try!(h.extra_field.write_le_u16(id));
+                try!(h.extra_field.write_le_u16(field.len() as u16));
+                h.extra_field.extend_from_slice(field);
+            }
+        }
+        self.write_entry(h, f.uncompressed_size, &data[..], false)
+    }
+
+    fn finish_file(&mut self) -> Result<(), ZipError> {
+        let PendingFile { header: mut h, streamed_from, data, crc32, size, large_file } =
+            match self.current.take() {
+                Some(file) => file,
+                None => return Ok(())
+            };
+        h.crc32 = crc32;
+        h.version_made_by = self.made_by(&h);
+        if let Some(offset) = streamed_from {
+            let dd = format::DataDescriptor { signature_present: true, crc32: crc32,
+                                              compressed_size: size, uncompressed_size: size };
+            try!(dd.write(&mut self.writer, large_file));
+            self.offset += if large_file { 24 } else { 16 };
+            set_central_fields(&mut h, size, size, offset, large_file);
+            self.files.push(h);
+            return Ok(());
+        }
+        match CompressionMethod::from_u16(h.compression_method) {
+            CompressionMethod::Store => {
+                h.version_needed_to_extract = 10;
+                self.write_entry(h, size, &data[..], large_file)
+            },
+            CompressionMethod::Deflate => {
+                h.version_needed_to_extract = 20;
+                let compressed = flate::deflate_bytes(&data[..]);
+                self.write_entry(h, size, &compressed[..], large_file)
+            },
+            _ => unreachable!("start_file_with_options only lets Store and Deflate through")
+        }
+    }
+
+    // entries with a Unix mode are made on Unix, or extractors ignore the mode
+    fn made_by(&self, h: &format::CentralDirectoryHeader) -> u16 {
+        if h.external_file_attributes >> 16 != 0 {
+            (3 << 8) | (self.version_made_by & 0xff)
+        } else {
+            self.version_made_by
+        }
+    }
+
+    // writes the local header and data of an entry, keeping its header for the central directory
+    fn write_entry(&mut self, mut h: format::CentralDirectoryHeader, uncompressed_size: u64,
+                   data: &[u8], large_file: bool) -> Result<(), ZipError> {
+        let compressed_size = data.len() as u64;
+        let offset = self.offset;
+        let zip64_sizes = set_central_fields(&mut h, uncompressed_size, compressed_size, offset, large_file);
+        try!(self.write_local_header(&h, uncompressed_size, compressed_size, zip64_sizes));
+        try!(self.writer.write_all(data));
+        self.offset += compressed_size;
+        self.files.push(h);
+        Ok(())
+    }
+
+    fn write_local_header(&mut self, h: &format::CentralDirectoryHeader, uncompressed_size: u64,
+                          compressed_size: u64, zip64_sizes: bool) -> Result<(), ZipError> {
+        let mut local = local_file_header(h);
+        if zip64_sizes {
+            // the local header has to give both sizes
+            local.extra_field = format::zip64_extra_field(&[uncompressed_size, compressed_size]);
+        }
+        try!(local.write(&mut self.writer));
+        self.offset += local.total_size();
+        Ok(())
+    }
+
+    /// Finishes the last entry and writes the central directory,
+    /// returning the underlying writer.
+    /// Archives with more than 65534 entries, or whose central directory starts past 4 GB or
+    /// is larger than that, get the Zip64 end records; so do those with a Zip64 entry.
+    pub fn finish(mut self) -> Result<W, ZipError> {
+        try!(self.finish_file());
+        let directory_offset = self.offset;
+        let mut directory_size = 0u64;
+        for h in self.files.iter() {
+            try!(h.write(&mut self.writer));
+            directory_size += h.total_size();
+        }
+        let entry_count = self.files.len() as u64;
+        let has_zip64_entry = self.files.iter().any(|h| {
+            format::find_extra_field(&h.extra_field, format::ZIP64_EXTRA_FIELD_ID).is_some()
+        });
+        if entry_count >= 0xffff || directory_size >= 0xffffffff || directory_offset >= 0xffffffff ||
+           has_zip64_entry {
+            let mut record = format::Zip64EndOfCentralDirectoryRecord::new();
+            record.version_made_by = self.version_made_by;
+            record.entry_count_this_disk = entry_count;
+            record.total_entry_count = entry_count;
+            record.central_directory_size = directory_size;
+            record.central_directory_offset = directory_offset;
+            try!(record.write(&mut self.writer));
+            let mut locator = format::Zip64EndOfCentralDirectoryLocator::new();
+            locator.zip64_end_record_offset = directory_offset + directory_size;
+            try!(locator.write(&mut self.writer));
+        }
+        // fields that don't fit are left to the Zip64 record
+        let mut end = format::EndOfCentralDirectoryRecord::new();
+        end.entry_count_this_disk = cmp::min(entry_count, 0xffff) as u16;
+        end.total_entry_count = cmp::min(entry_count, 0xffff) as u16;
+        end.central_directory_size = cmp::min(directory_size, 0xffffffff) as u32;
+        end.central_directory_offset = cmp::min(directory_offset, 0xffffffff) as u32;
+        end.comment = self.comment;
+        try!(end.write(&mut self.writer));
+        Ok(self.writer)
+    }
+}
+
+impl<W:Write+Seek> ZipWriter<W> {
+    /// Adds entries to the existing archive in `inner`, opened for reading as `reader`.
+    /// The new entries are written over the old central directory, and `finish` then writes
+    /// a central directory covering the old entries as well. The archive comment is kept,
+    /// which also makes sure nothing of the old directory is left past the new end.
+    pub fn new_append<R:Read+Seek>(mut inner: W, mut reader: ZipReader<R>) -> Result<ZipWriter<W>, ZipError> {
+        let files = try!(reader.central_directory_headers());
+        let offset = reader.central_directory_offset();
+        try!(inner.seek(SeekFrom::Start(offset)));
+        let mut w = ZipWriter::new(inner);
+        w.offset = offset;
+        w.files = files;
+        w.comment = reader.end_record().comment.clone();
+        Ok(w)
+    }
+}
+
+impl<W:Write> Write for ZipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let file = match self.current {
+            Some(ref mut file) => file,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "no file has been started"))
+        };
+        match file.streamed_from {
+            Some(_) => {
+                // the local header already went out without the Zip64 extra field
+                if !file.large_file && file.size + buf.len() as u64 > 0xffffffff {
+                    return Err(io::Error::new(io::ErrorKind::Other, ZipError::EntryTooLarge));
+                }
+                try!(self.writer.write_all(buf));
+                self.offset += buf.len() as u64;
+            },
+            None => file.data.extend_from_slice(buf)
+        }
+        file.crc32 = crc32::update(file.crc32, buf);
+        file.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    // data written with a data descriptor has been passed on already, the rest waits for
+    // the end of its entry
+    fn flush(&mut self) -> io::Result<()> { self.writer.flush() }
+}
+
+fn new_header(name: MaybeUtf8Slice) -> format::CentralDirectoryHeader {
+    let mut h = format::CentralDirectoryHeader::new();
+    // flag non-ASCII names as UTF-8 when they are
+    if name.as_str().is_some() && name.as_bytes().iter().any(|&b| b >= 0x80) {
+        h.general_purpose_bit_flag |= 2048;
+    }
+    h.file_name = name.to_owned();
+    h
+}
+
+// the header of a new entry, as far as `options` tell
+fn entry_header(name: MaybeUtf8Slice, options: &FileOptions) -> Result<format::CentralDirectoryHeader, ZipError> {
+    let mut h = new_header(name);
+    h.compression_method = options.compression_method.to_u16();
+    h.last_modified_datetime = match options.last_modified {
+        Some(Timestamp::DateTime(year, month, day, hour, minute, second)) =>
+            try!(format::MsdosDateTime::try_new(year, month, day, hour, minute, second)),
+        Some(Timestamp::UnixTime(time)) => try!(format::MsdosDateTime::from_unix_time(time)),
+        None => format::MsdosDateTime::zero()
+    };
+    if let Some(mode) = options.unix_mode {
+        h.external_file_attributes = mode << 16;
+        if h.file_name.as_bytes().last() == Some(&b'/') {
+            h.external_file_attributes |= MSDOS_DIRECTORY_ATTRIBUTE;
+        }
+    }
+    Ok(h)
+}
+
+// copies `r` to `w` a chunk at a time, returning the crc32 and length of what was copied
+fn copy_counting<R:Read, T:Write>(r: &mut R, w: &mut T) -> io::Result<(u32, u64)> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let (mut crc, mut len) = (0, 0u64);
+    loop {
+        let n = match r.read(&mut buf) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        };
+        if n == 0 {
+            return Ok((crc, len));
+        }
+        try!(w.write_all(&buf[..n]));
+        crc = crc32::update(crc, &buf[..n]);
+        len += n as u64;
+    }
+}
+
+// fills in the sizes and local header offset of a central directory header; those that don't
+// fit in their field go to a Zip64 extra field, as do the sizes of a `large_file`. Returns
+// whether the sizes did.
+fn set_central_fields(h: &mut format::CentralDirectoryHeader, uncompressed_size: u64,
+                      compressed_size: u64, offset: u64, large_file: bool) -> bool {
+    let zip64_sizes = large_file || uncompressed_size > 0xffffffff || compressed_size > 0xffffffff;
+    let zip64_offset = offset > 0xffffffff;
+    let mut central_values = Vec::new();
+    if zip64_sizes {
+        h.uncompressed_size = 0xffffffff;
+        h.compressed_size = 0xffffffff;
+        central_values.push(uncompressed_size);
+        central_values.push(compressed_size);
+    } else {
+        h.uncompressed_size = uncompressed_size as u32;
+        h.compressed_size = compressed_size as u32;
+    }
+    if zip64_offset {
+        h.relative_offset_of_local_header = 0xffffffff;
+        central_values.push(offset);
+    } else {
+        h.relative_offset_of_local_header = offset as u32;
+    }
+    if zip64_sizes || zip64_offset {
+        h.version_needed_to_extract = cmp::max(h.version_needed_to_extract, ZIP64_VERSION);
+        h.extra_field.extend(format::zip64_extra_field(&central_values[..]));
+    }
+    zip64_sizes
+}
+
+// the local header repeats most of the central directory header
+fn local_file_header(h: &format::CentralDirectoryHeader) -> format::LocalFileHeader {
+    let mut local = format::LocalFileHeader::new();
+    local.version_needed_to_extract = h.version_needed_to_extract;
+    local.general_purpose_bit_flag = h.general_purpose_bit_flag;
+    local.compression_method = h.compression_method;
+    local.last_modified_datetime = h.last_modified_datetime.clone();
+    local.crc32 = h.crc32;
+    local.compressed_size = h.compressed_size;
+    local.uncompressed_size = h.uncompressed_size;
+    local.file_name = h.file_name.clone();
+    local
+}